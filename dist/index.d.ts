@@ -7,6 +7,12 @@ export interface Item {
 	total: string;
 	/** Pre-discount unit price. When set and > price, a discount line is shown. */
 	originalPrice?: string;
+	/** Order time (e.g. "14:05"), printed small at the left of the item's first line */
+	time?: string;
+	/** Combo/bundle contents, listed indented and smaller under the item (price stays on the item line) */
+	components?: string[];
+	/** Product thumbnail (PNG/JPEG, max 256 KB), printed at most 56×56 dots at the start of the row */
+	image?: Buffer;
 }
 
 export interface Footer {
@@ -16,6 +22,13 @@ export interface Footer {
 	phones?: string;
 }
 
+export interface Watermark {
+	/** Encoded image (PNG/JPEG), scaled to the paper width and tiled down the receipt */
+	bytes: Buffer;
+	/** 0..1, density of the dithered background (defaults to 0.15) */
+	opacity?: number;
+}
+
 export interface PrintPayload {
 	title: string;
 	time: string;
@@ -23,13 +36,81 @@ export interface PrintPayload {
 	items: Item[];
 	total: string;
 	discount?: string;
+	/** Service fee row ("رسوم الخدمة") in the totals block; counted in the computed total */
+	serviceFee?: string;
+	/** Delivery fee row ("رسوم التوصيل") in the totals block; counted in the computed total */
+	deliveryFee?: string;
+	/**
+	 * What to do when `total` differs from the sum of item totals minus `discount` plus fees:
+	 * "trust" (default) prints it as given, "recompute" prints the computed value,
+	 * "error" rejects the payload (tolerance 0.01) before anything is printed.
+	 */
+	totalMismatchPolicy?: "trust" | "recompute" | "error";
+	/**
+	 * Round the printed grand total to the nearest step (e.g. 0.05) and add a "تقريب" adjustment row.
+	 * The reconciliation barcode keeps the exact total.
+	 */
+	cashRounding?: number;
+	/** Add a fifth column ("الرصيد") with the cumulative total through each item (tabs / running bills) */
+	runningBalance?: boolean;
+	/** Crop the raster at the last printed row instead of the layout height (saves paper) */
+	autoTrimBottom?: boolean;
+	/** Blank dots kept below the last printed row when `autoTrimBottom` is on (default 0) */
+	autoTrimMargin?: number;
 	footer: Footer;
+	/**
+	 * Script of the payload text (defaults to "arabic"). "hebrew"/"none" skip Arabic reshaping but keep
+	 * RTL run ordering and right alignment. Built-in labels stay Arabic.
+	 */
+	script?: "arabic" | "hebrew" | "none";
+	/** TrueType/OpenType font for payload text instead of the bundled Noto Sans Arabic (required for Hebrew glyphs); the built-in Arabic labels and totals keep the bundled font */
+	fontBytes?: Buffer;
 	/** Optional UUID/nanoid to print as a 1D Code 128 barcode at the bottom */
 	uuid?: string;
+	/** Faint background image (e.g. store logo) printed behind the text */
+	watermark?: Watermark;
+	/** Tear-line marker (e.g. "✂- - ") repeated across the last row before the cut; glyphs missing from the font print as "-" */
+	endMarker?: string;
+	/** Boxed summary (item count, total, order type) between the header and the item table */
+	summaryHeader?: boolean;
+	/** Order type shown in the summary box, e.g. "توصيل" */
+	orderType?: string;
+	/** Print a Code 128 barcode carrying `number` + delimiter + total (e.g. "123456|97.20") for reconciliation scanners */
+	reconcileBarcode?: boolean;
+	/**
+	 * Delimiter between number and total in the reconciliation barcode (defaults to "|").
+	 * Must be printable ASCII; only the ASCII letters/digits of `number` are encoded ("فاتورة رقم: 42" -> "42").
+	 * The whole barcode string is limited to 23 characters, the most that fit across 80mm paper.
+	 */
+	reconcileDelimiter?: string;
+	/** "receipt" (default) is cut after printing; "label" is advanced to the next label (GS FF) */
+	media?: "receipt" | "label";
+	/** Override the finisher: receipts cut unless `false`, labels only cut when `true` */
+	cut?: boolean;
+	/** Model-specific init sequence sent after ESC @ (defaults to "generic": nothing extra) */
+	printerProfile?: "generic" | "epson-tm" | "xprinter" | "custom";
+	/** Raw init bytes for `printerProfile: "custom"` */
+	initBytes?: Buffer;
 	/** Serial port (defaults via env PRINTER_COM_PORT or COM7 on Windows) */
 	port?: string;
 	/** Baud (defaults via env PRINTER_BAUD_RATE or 9600) */
 	baud?: number;
+	/**
+	 * Serial write timeout in ms (defaults to the serialport default). Short writes are retried by the
+	 * driver; a port that stays saturated past this timeout fails the print instead of truncating it.
+	 */
+	writeTimeoutMs?: number;
+	/**
+	 * When the port cannot be opened, write the exact byte stream to `<spoolDir>/<port>/<unix-ms>.bin`
+	 * and resolve with a "spooled" message instead of rejecting.
+	 */
+	spoolOnFailure?: boolean;
+	/** Spool root for `spoolOnFailure` (defaults to `<os tmp>/pos-receipt-printer-spool`) */
+	spoolDir?: string;
+	/** Hex-dump every command sent to the printer (for field diagnostics) */
+	debug?: boolean;
+	/** File the `debug` dump is appended to (defaults to stderr; also used with a warning when the file can't be opened) */
+	debugLog?: string;
 }
 
 /**
@@ -37,4 +118,10 @@ export interface PrintPayload {
  */
 export function printReceipt(payload: PrintPayload): Promise<string>;
 
+/**
+ * Print several receipts/labels over a single connection (connection settings from the first payload).
+ * Each payload is finished according to its own `media`/`cut`.
+ */
+export function printBatch(payloads: PrintPayload[]): Promise<string>;
+
 export as namespace PosReceiptPrinter;
@@ -1,12 +1,18 @@
 use napi::bindgen_prelude::*;
 use napi_derive::napi;
 
-use escpos::{driver::SerialPortDriver, printer::Printer, utils::*};
-use image::{DynamicImage, ImageBuffer, Rgb, RgbImage, GrayImage, Luma};
+use escpos::{driver::{Driver, SerialPortDriver}, printer::Printer, utils::*};
+use image::{imageops::{self, FilterType}, ImageBuffer, GrayImage, Luma};
 use imageproc::drawing::draw_text_mut;
 use rusttype::{Font, Scale, point, PositionedGlyph};
 use ar_reshaper::reshape_line;
 use serde::Deserialize;
+use std::cell::RefCell;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 // ===================== Defaults =====================
 const DEFAULT_COM_PORT: &str = "COM7";
@@ -44,8 +50,13 @@ struct Item {
     price: f32,
     original_price: Option<f32>,
     price_str: String,
+    total: f64, // f64 so sums stay exact to the cent
     total_str: String,
     original_price_str: Option<String>,
+    time: Option<String>,
+    components: Vec<String>,
+    #[serde(skip)]
+    thumbnail: Option<GrayImage>, // dithered, at most THUMB_MAX_PX on each side
 }
 
 #[derive(Clone)]
@@ -54,13 +65,29 @@ struct ReceiptData {
     date_time_line: String,
     invoice_no: String,
     items: Vec<Item>,
-    discount: f32,
-    total_str: String,
+    discount: f64,
+    total_str: String,            // displayed grand total (rounded when cashRounding is set)
+    rounding_adjustment: Option<String>,
     discount_str: String,
+    service_fee: f64,
+    service_fee_str: String,
+    delivery_fee: f64,
+    delivery_fee_str: String,
     footer_address: String,
     footer_delivery: String,
     footer_phones: String,
     uuid: Option<String>,
+    reconcile_barcode: Option<String>,
+    watermark: Option<Watermark>,
+    end_marker: Option<String>,
+    summary_header: bool,
+    order_type: Option<String>,
+}
+
+#[derive(Clone)]
+struct Watermark {
+    image: GrayImage, // already scaled to paper width; tiled down the receipt
+    opacity: f32,
 }
 
 #[derive(Clone)]
@@ -73,6 +100,12 @@ struct Layout {
     row_gap: i32,
     fonts: Fonts,
     cols: [f32; 4], // [name, qty, price, total] (fractions of inner width)
+    cols_running: [f32; 5], // [name, qty, price, total, balance] when running_balance is on
+    running_balance: bool,
+    auto_trim_bottom: bool,
+    auto_trim_margin: u32, // blank dots kept below the last inked row when auto_trim_bottom is on
+    script: Script,
+    font: Option<Font<'static>>,
 }
 #[derive(Clone)]
 struct Fonts {
@@ -81,6 +114,7 @@ struct Fonts {
     header_no: f32,
     header_cols: f32,
     item: f32,
+    item_time: f32,
     total_label: f32,
     total_value: f32,
     footer: f32,
@@ -101,12 +135,19 @@ impl Default for Layout {
                 header_no: 46.0,
                 header_cols: 42.0,
                 item: 44.0,
+                item_time: 26.0,
                 total_label: 48.0,
                 total_value: 66.0,
                 footer: 45.0,
                 footer_phones: 56.0,
             },
             cols: [0.60, 0.11, 0.12, 0.17],
+            cols_running: [0.42, 0.10, 0.12, 0.14, 0.22],
+            running_balance: false,
+            auto_trim_bottom: false,
+            auto_trim_margin: 0,
+            script: Script::Arabic,
+            font: None,
         }
     }
 }
@@ -120,6 +161,14 @@ pub struct JsItem {
     pub price: String,
     pub total: String,
     pub originalPrice: Option<String>,
+    pub time: Option<String>,
+    pub components: Option<Vec<String>>,
+    pub image: Option<Buffer>,
+}
+#[napi(object)]
+pub struct JsWatermark {
+    pub bytes: Buffer,
+    pub opacity: Option<f64>,
 }
 #[napi(object)]
 #[allow(non_snake_case)]
@@ -129,6 +178,7 @@ pub struct JsFooter {
     pub phones: Option<String>,
 }
 #[napi(object)]
+#[allow(non_snake_case)]
 pub struct JsPrintPayload {
     pub title: String,
     pub time: String,
@@ -136,121 +186,247 @@ pub struct JsPrintPayload {
     pub items: Vec<JsItem>,
     pub total: String,
     pub discount: Option<String>,
+    pub serviceFee: Option<String>,
+    pub deliveryFee: Option<String>,
     pub footer: JsFooter,
     pub uuid: Option<String>,
+    pub watermark: Option<JsWatermark>,
+    pub endMarker: Option<String>,
+    pub summaryHeader: Option<bool>,
+    pub orderType: Option<String>,
+    pub reconcileBarcode: Option<bool>,
+    pub reconcileDelimiter: Option<String>,
+    pub media: Option<String>,
+    pub cut: Option<bool>,
+    pub printerProfile: Option<String>,
+    pub totalMismatchPolicy: Option<String>,
+    pub cashRounding: Option<f64>,
+    pub script: Option<String>,
+    pub runningBalance: Option<bool>,
+    pub autoTrimBottom: Option<bool>,
+    pub autoTrimMargin: Option<u32>,
+    pub fontBytes: Option<Buffer>,
+    pub initBytes: Option<Buffer>,
     pub port: Option<String>,
     pub baud: Option<u32>,
+    pub writeTimeoutMs: Option<u32>,
+    pub spoolOnFailure: Option<bool>,
+    pub spoolDir: Option<String>,
+    pub debug: Option<bool>,
+    pub debugLog: Option<String>,
 }
 
 // ===================== Text shaping/measurement =====================
-fn shape(s: &str) -> String { reshape_line(s) }
+// Which shaping the payload text needs. Only Arabic joins letters into presentation forms;
+// other RTL scripts (Hebrew) keep the run ordering and right alignment but are drawn as-is.
+#[derive(Clone, Copy, PartialEq)]
+enum Script {
+    Arabic,
+    Hebrew,
+    None,
+}
 
-// Treat spaces as neutral (do NOT force them into LTR).
-fn is_ltr_char(c: char) -> bool {
-    if c == ' ' || c == '\u{00A0}' { return false; }              // neutral spaces
-    if c.is_ascii_alphanumeric() { return true; }                  // Latin letters/digits
-    if ('\u{0660}'..='\u{0669}').contains(&c)                      // Arabic-Indic digits
-        || ('\u{06F0}'..='\u{06F9}').contains(&c) { return true; }
-    matches!(c, ':'|'.'|','|'-'|'–'|'—'|'/')                       // some punctuation as LTR
+fn shape(s: &str, script: Script) -> String {
+    match script {
+        Script::Arabic => reshape_line(s),
+        Script::Hebrew | Script::None => s.to_string(),
+    }
 }
 
-// Accurate width including spaces using rusttype layout
-fn measure(scale: Scale, font: &Font, s: &str) -> i32 {
+// Strong direction of a char: Some(true) LTR, Some(false) RTL, None for neutrals
+// (spaces and the punctuation that sits between numbers and words).
+fn char_dir(c: char) -> Option<bool> {
+    if c == ' ' || c == '\u{00A0}' { return None; }                // neutral spaces
+    if matches!(c, ':'|'.'|','|'-'|'–'|'—'|'/') { return None; }   // neutral punctuation
+    if c.is_ascii_alphanumeric() { return Some(true); }              // Latin letters/digits
+    if ('\u{0660}'..='\u{0669}').contains(&c)                        // Arabic-Indic digits
+        || ('\u{06F0}'..='\u{06F9}').contains(&c) { return Some(true); }
+    Some(false)
+}
+
+// Splits shaped text into (is_ltr, text) runs in logical order. Neutrals take the direction of the
+// strong text on both sides when it agrees and the RTL base direction otherwise (UAX #9 N1/N2):
+// "12.50" stays one LTR run, while in "فاتورة رقم: 42" the ": " joins the Arabic run.
+fn bidi_runs(shaped: &str) -> Vec<(bool, String)> {
+    let chars: Vec<char> = shaped.chars().collect();
+    let dirs: Vec<Option<bool>> = chars.iter().map(|&c| char_dir(c)).collect();
+    let mut runs: Vec<(bool, String)> = Vec::new();
+
+    for (i, &ch) in chars.iter().enumerate() {
+        let ltr = dirs[i].unwrap_or_else(|| {
+            let prev = dirs[..i].iter().rev().find_map(|d| *d);
+            let next = dirs[i + 1..].iter().find_map(|d| *d);
+            prev == Some(true) && next == Some(true)
+        });
+        if let Some((kind, seg)) = runs.last_mut() {
+            if *kind == ltr { seg.push(ch); continue; }
+        }
+        runs.push((ltr, ch.to_string()));
+    }
+    runs
+}
+
+// RTL letters: Hebrew, Arabic (base block, supplement and presentation forms)
+fn has_rtl(s: &str) -> bool {
+    s.chars().any(|c| matches!(c,
+        '\u{0590}'..='\u{05FF}' | '\u{FB1D}'..='\u{FB4F}'
+        | '\u{0600}'..='\u{065F}' | '\u{066A}'..='\u{06EF}' | '\u{06FA}'..='\u{06FF}'
+        | '\u{0750}'..='\u{077F}' | '\u{FB50}'..='\u{FDFF}' | '\u{FE70}'..='\u{FEFF}'))
+}
+
+// Accurate width including spaces using rusttype layout (unrounded)
+fn advance(scale: Scale, font: &Font, s: &str) -> f32 {
     let mut x = 0.0f32;
     for g in font.layout(s, scale, point(0.0, 0.0)) {
         x += g.unpositioned().h_metrics().advance_width;
     }
-    x.round() as i32
+    x
+}
+
+fn measure(scale: Scale, font: &Font, s: &str) -> i32 {
+    advance(scale, font, s).round() as i32
+}
+
+// Width of a logical (unshaped) string exactly as draw_mixed_rtl_* will lay it out.
+// Wrapping and alignment must measure through this so decisions match rendered widths.
+fn shaped_width(scale: Scale, font: &Font, script: Script, logical: &str) -> i32 {
+    measure(scale, font, &shape(logical, script))
 }
 
-fn draw_crisp(img: &mut RgbImage, s: &str, x: i32, y: i32, scale: Scale, font: &Font) {
-    draw_text_mut(img, Rgb([0,0,0]), x, y, scale, font, s);
+fn draw_crisp(img: &mut GrayImage, s: &str, x: i32, y: i32, scale: Scale, font: &Font) {
+    draw_text_mut(img, Luma([0]), x, y, scale, font, s);
 }
 
-fn draw_ltr_right(img: &mut RgbImage, font: &Font, scale: Scale, s: &str, x_right: i32, y: i32) {
+fn draw_ltr_right(img: &mut GrayImage, font: &Font, scale: Scale, s: &str, x_right: i32, y: i32) {
     let w = measure(scale, font, s);
     draw_crisp(img, s, x_right - w, y, scale, font);
 }
 
-fn draw_ltr_center(img: &mut RgbImage, font: &Font, scale: Scale, s: &str, paper_w: i32, y: i32) {
+fn draw_ltr_center(img: &mut GrayImage, font: &Font, scale: Scale, s: &str, paper_w: i32, y: i32) {
     let w = measure(scale, font, s);
     draw_crisp(img, s, (paper_w - w)/2, y, scale, font);
 }
 
 // Mixed RTL/LTR drawing (right aligned). Spaces are preserved.
-fn draw_mixed_rtl_right(img: &mut RgbImage, font: &Font, scale: Scale, logical: &str, x_right: i32, y: i32) {
-    let shaped = shape(logical);
-    // Segment into runs based on LTR/RTL; spaces join to previous run to preserve spacing.
-    let mut runs: Vec<(bool, String)> = Vec::new(); // (is_ltr, text)
-    let mut cur = String::new();
-    let mut cur_is_ltr: Option<bool> = None;
+fn draw_mixed_rtl_right(img: &mut GrayImage, font: &Font, script: Script, scale: Scale, logical: &str, x_right: i32, y: i32) {
+    draw_shaped_rtl_right(img, font, scale, &shape(logical, script), x_right, y);
+}
 
-    for ch in shaped.chars() {
-        let is_space = ch == ' ' || ch == '\u{00A0}';
-        let ltr = if is_space { cur_is_ltr.unwrap_or(false) } else { is_ltr_char(ch) };
-        match cur_is_ltr {
-            None => { cur_is_ltr = Some(ltr); cur.push(ch); }
-            Some(kind) if kind == ltr || is_space => cur.push(ch),
-            Some(_) => { runs.push((cur_is_ltr.unwrap(), cur.clone())); cur.clear(); cur_is_ltr = Some(ltr); cur.push(ch); }
-        }
-    }
-    if !cur.is_empty() { runs.push((cur_is_ltr.unwrap_or(false), cur)); }
+// Draws text that has already been through `shape` exactly once; reshaping presentation
+// forms again is not guaranteed to be a no-op.
+fn draw_shaped_rtl_right(img: &mut GrayImage, font: &Font, scale: Scale, shaped: &str, x_right: i32, y: i32) {
+    let runs = bidi_runs(shaped);
 
-    let mut right = x_right;
+    // Positions accumulate unrounded advances so the drawn extent equals measure(shaped);
+    // rounding per glyph/run drifts by up to half a pixel each on long names.
+    let mut right = x_right as f32;
 
     for (is_ltr, seg) in runs.into_iter() {
-        let seg_w = measure(scale, font, &seg);
+        let seg_w = advance(scale, font, &seg);
         if is_ltr {
-            draw_ltr_right(img, font, scale, &seg, right, y);
+            draw_ltr_right(img, font, scale, &seg, right.round() as i32, y);
         } else {
             // Draw RTL char-by-char (right to left), keeping spaces
             let mut x = right - seg_w;
             for ch in seg.chars().rev() {
                 let s = ch.to_string();
-                let cw = measure(scale, font, &s);
-                draw_crisp(img, &s, x, y, scale, font);
-                x += cw;
+                draw_crisp(img, &s, x.round() as i32, y, scale, font);
+                x += advance(scale, font, &s);
             }
         }
         right -= seg_w;
     }
 }
 
-fn draw_mixed_rtl_center(img: &mut RgbImage, font: &Font, scale: Scale, logical: &str, paper_w: i32, y: i32) {
-    let shaped = shape(logical);
+fn draw_mixed_rtl_center(img: &mut GrayImage, font: &Font, script: Script, scale: Scale, logical: &str, paper_w: i32, y: i32) {
+    let shaped = shape(logical, script);
     let w = measure(scale, font, &shaped);
     let x = (paper_w - w)/2;
-    draw_mixed_rtl_right(img, font, scale, &shaped, x + w, y);
+    draw_shaped_rtl_right(img, font, scale, &shaped, x + w, y);
+}
+
+// Vertical middle of digit ink, measured from the y passed to draw_text_mut (baseline = y + ascent).
+fn digit_ink_center(font: &Font, scale: Scale) -> f32 {
+    let ascent = font.v_metrics(scale).ascent;
+    let mid = font.glyph('0').scaled(scale).exact_bounding_box()
+        .map(|bb| -(bb.min.y + bb.max.y) / 2.0)
+        .unwrap_or(0.0);
+    ascent - mid
+}
+
+// y offset for text at `big` so it shares a vertical center with text at `small` drawn at the same
+// y. Both the ascent and the ink height grow with the size, so this comes from the font metrics
+// rather than a fixed pixel nudge.
+fn center_offset(font: &Font, small: Scale, big: Scale) -> i32 {
+    (digit_ink_center(font, small) - digit_ink_center(font, big)).round() as i32
+}
+
+// Vertical ink extent (top, bottom) of already-shaped strings relative to the draw y, from the glyph
+// bounds rather than the line metrics, which leave a font-dependent gap above and below the ink.
+// Glyphs the font lacks are skipped; with no ink at all this falls back to (0, ascent).
+fn ink_extent(font: &Font, scale: Scale, shaped: &[String]) -> (i32, i32) {
+    let ascent = font.v_metrics(scale).ascent;
+    let (mut top, mut bottom) = (f32::MAX, f32::MIN);
+    for g in shaped.iter().flat_map(|s| font.layout(s, scale, point(0.0, 0.0))) {
+        if g.id().0 == 0 { continue; }
+        if let Some(bb) = g.unpositioned().exact_bounding_box() {
+            top = top.min(bb.min.y);
+            bottom = bottom.max(bb.max.y);
+        }
+    }
+    if top > bottom { return (0, ascent.round() as i32); }
+    ((ascent + top).floor() as i32, (ascent + bottom).ceil() as i32)
+}
+
+// Totals-block row: built-in Arabic label at the right edge, LTR amount just left of it.
+fn draw_total_row(img: &mut GrayImage, font: &Font, scale: Scale, label: &str, value: &str, right: i32, y: i32) {
+    let gap = 12;
+    let lw = shaped_width(scale, font, Script::Arabic, label);
+    draw_ltr_right(img, font, scale, value, right - lw - gap, y);
+    draw_mixed_rtl_right(img, font, Script::Arabic, scale, label, right, y);
+}
+
+// Hollow rectangle with `t`-pixel sides, clipped to the image
+fn draw_box(img: &mut GrayImage, left: i32, top: i32, right: i32, bottom: i32, t: i32) {
+    let (w, h) = (img.width() as i32, img.height() as i32);
+    for yy in top.max(0)..bottom.min(h) {
+        for xx in left.max(0)..right.min(w) {
+            let edge = yy < top + t || yy >= bottom - t || xx < left + t || xx >= right - t;
+            if edge { img.put_pixel(xx as u32, yy as u32, Luma([0])); }
+        }
+    }
 }
 
 // Simple dotted separator
-fn draw_dotted(img: &mut RgbImage, y: i32, left: i32, right: i32) {
+fn draw_dotted(img: &mut GrayImage, y: i32, left: i32, right: i32) {
     let y = y.max(0) as u32;
     let mut x = left.max(0);
     while x < right {
         for dx in 0..3 {
-            if x + dx < right { img.put_pixel((x + dx) as u32, y, Rgb([0,0,0])); }
+            if x + dx < right { img.put_pixel((x + dx) as u32, y, Luma([0])); }
         }
         x += 10;
     }
 }
 
 // ====== Wrapping (max 2 lines with ellipsis) ======
-fn wrap_mixed_rtl(font: &Font, scale: Scale, logical: &str, max_w: i32) -> Vec<String> {
+fn wrap_mixed_rtl(font: &Font, script: Script, scale: Scale, logical: &str, max_w: i32) -> Vec<String> {
     // Keep whitespace tokens with split_inclusive so we never drop spaces
     let tokens: Vec<&str> = logical.split_inclusive(char::is_whitespace).collect();
     let mut out: Vec<String> = Vec::new();
     let mut line = String::new();
+    let mut truncated = false;
 
     for tok in tokens {
+        // Lines are drawn trimmed, so measure them trimmed (a trailing space would wrap early)
         let test = format!("{}{}", line, tok);
-        let test_w = measure(scale, font, &shape(&test));
+        let test_w = shaped_width(scale, font, script, test.trim_end());
         if test_w <= max_w || line.is_empty() {
             line.push_str(tok);
         } else {
             out.push(line.trim_end().to_string());
             line = tok.to_string();
-            if out.len() == 2 { break; }
+            if out.len() == 2 { truncated = true; break; }
         }
     }
     if out.len() < 2 && !line.is_empty() {
@@ -261,11 +437,11 @@ fn wrap_mixed_rtl(font: &Font, scale: Scale, logical: &str, max_w: i32) -> Vec<S
     if out.len() > 2 {
         out.truncate(2);
     }
-    if out.len() == 2 {
+    if out.len() == 2 && truncated {
         // ensure second line fits with ellipsis if needed
         let ell = "…";
         let mut s2 = out[1].clone();
-        while measure(scale, font, &shape(&(s2.clone() + ell))) > max_w && !s2.is_empty() {
+        while shaped_width(scale, font, script, &format!("{}{}", s2.trim_end(), ell)) > max_w && !s2.is_empty() {
             s2.pop();
         }
         out[1] = format!("{}{}", s2.trim_end(), ell);
@@ -274,68 +450,228 @@ fn wrap_mixed_rtl(font: &Font, scale: Scale, logical: &str, max_w: i32) -> Vec<S
 }
 
 // ===================== Rendering =====================
+// Payload font (needed for scripts the bundled Noto Sans Arabic has no glyphs for), else the bundled one.
+// Widths and right edges of the item table; balance is only meaningful with cols_running
+struct Columns {
+    w_name: i32,
+    r_name: i32,
+    r_qty: i32,
+    r_price: i32,
+    r_total: i32,
+    r_balance: i32,
+}
+fn columns(cols: &[f32], inner_w: i32, right_edge: i32) -> Columns {
+    let w_name    = (inner_w as f32 * cols[0]) as i32;
+    let w_qty     = (inner_w as f32 * cols[1]) as i32;
+    let w_total   = (inner_w as f32 * cols[3]) as i32;
+
+    let r_name    = right_edge;
+    let r_qty     = r_name  - w_name;
+    let r_price   = r_qty   - w_qty;
+    let r_total   = r_price - w_total;
+    // The balance sits left of the whole total column
+    let r_balance = r_total - w_total;
+    Columns { w_name, r_name, r_qty, r_price, r_total, r_balance }
+}
+
+fn bundled_font() -> Font<'static> {
+    let font_bytes: &'static [u8] = include_bytes!("fonts/NotoSansArabic-Regular.ttf");
+    Font::try_from_bytes(font_bytes).expect("font")
+}
+
+// Font for payload text; the built-in Arabic labels always use `bundled_font`.
+fn load_font(layout: &Layout) -> Font<'static> {
+    if let Some(font) = &layout.font { return font.clone(); }
+    bundled_font()
+}
+
+// Glyphs the font lacks (e.g. "✂") would print as tofu boxes; swap them for a dash.
+fn substitute_tofu(font: &Font, s: &str) -> String {
+    s.chars()
+        .map(|c| if c.is_whitespace() || font.glyph(c).id().0 != 0 { c } else { '-' })
+        .collect()
+}
+
+// Tear-line row: the marker repeated as many times as fits in `max_w`.
+fn marker_row(font: &Font, script: Script, scale: Scale, marker: &str, max_w: i32) -> String {
+    let unit = substitute_tofu(font, marker);
+    let mut row = unit.clone();
+    if shaped_width(scale, font, script, &unit) <= 0 { return row; }
+    while shaped_width(scale, font, script, &format!("{}{}", row, unit)) <= max_w {
+        row.push_str(&unit);
+    }
+    row
+}
+
+// Standalone strip for the end marker; it is sent after the barcodes so it really is the last row.
+fn render_end_marker(marker: &str, layout: &Layout) -> GrayImage {
+    let paper_w = layout.paper_width_px as i32;
+    let inner_w = paper_w - layout.margin_h*2;
+    let scale = Scale::uniform(layout.fonts.footer);
+    let h = layout.fonts.footer as u32 + 16;
+    let mut img: GrayImage = ImageBuffer::from_pixel(layout.paper_width_px, h, Luma([255]));
+
+    let font = load_font(layout);
+    let row = marker_row(&font, layout.script, scale, marker, inner_w);
+    draw_mixed_rtl_center(&mut img, &font, layout.script, scale, &row, paper_w, 0);
+
+    img
+}
+
+// Height of the scratch canvas a receipt is drawn into before it is cropped to its content.
+const CANVAS_HEIGHT: u32 = 2500;
+
 fn render_receipt(data: &ReceiptData, layout: &Layout) -> GrayImage {
     let paper_w = layout.paper_width_px as i32;
-    let mut img: RgbImage = ImageBuffer::from_pixel(layout.paper_width_px, 2500, Rgb([255,255,255]));
+    // Drawn straight into 8-bit gray: black text blended over white gives the same value in every
+    // RGB channel, so this is pixel-identical to the old RGB + to_luma8 path at a third of the memory.
+    let mut img: GrayImage = ImageBuffer::from_pixel(layout.paper_width_px, CANVAS_HEIGHT, Luma([255]));
     let margin_h = layout.margin_h;
     let inner_w = paper_w - margin_h*2;
     let right_edge = margin_h + inner_w;
     let mut y = layout.margin_top;
 
-    let font_bytes = include_bytes!("fonts/NotoSansArabic-Regular.ttf");
-    let font = Font::try_from_bytes(font_bytes).expect("font");
+    let font = load_font(layout);
+    // Headings and totals labels are Arabic whatever the payload script, so a fontBytes font
+    // without Arabic glyphs must not print them; they (and the amounts beside them) use the bundled font.
+    let label_font = bundled_font();
+    let script = layout.script;
 
     // Title
-    draw_mixed_rtl_center(&mut img, &font, Scale::uniform(layout.fonts.title), &data.store_name, paper_w, y);
+    draw_mixed_rtl_center(&mut img, &font, script, Scale::uniform(layout.fonts.title), &data.store_name, paper_w, y);
     y += layout.fonts.title as i32 - 8;
 
     // Date/Time
-    draw_mixed_rtl_center(&mut img, &font, Scale::uniform(layout.fonts.header_dt), &data.date_time_line, paper_w, y);
+    draw_mixed_rtl_center(&mut img, &font, script, Scale::uniform(layout.fonts.header_dt), &data.date_time_line, paper_w, y);
     y += layout.fonts.header_dt as i32 + 2;
 
-    // Receipt number (centered, plain LTR digits)
-    draw_ltr_center(&mut img, &font, Scale::uniform(layout.fonts.header_no), &data.invoice_no, paper_w, y);
+    // Receipt number (centered). Plain digits stay LTR; "فاتورة رقم: 42" style needs run ordering.
+    if has_rtl(&data.invoice_no) {
+        draw_mixed_rtl_center(&mut img, &font, script, Scale::uniform(layout.fonts.header_no), &data.invoice_no, paper_w, y);
+    } else {
+        draw_ltr_center(&mut img, &font, Scale::uniform(layout.fonts.header_no), &data.invoice_no, paper_w, y);
+    }
     y += layout.fonts.header_no as i32 + 2;
 
-    // Columns
-    let w_name  = (inner_w as f32 * layout.cols[0]) as i32;
-    let w_qty   = (inner_w as f32 * layout.cols[1]) as i32;
-    let w_price = (inner_w as f32 * layout.cols[2]) as i32;
-    let w_total = (inner_w as f32 * layout.cols[3]) as i32;
+    // Order summary box (item count, grand total, order type) ahead of the item table
+    if data.summary_header {
+        let s_sum = Scale::uniform(layout.fonts.header_cols);
+        let pad = 8;
+        let inset = 12;
+        let line_h = layout.row_gap;
+        let count = data.items.len().to_string();
+
+        let rows = [
+            vec![shape("عدد الأصناف", Script::Arabic), count.clone()],
+            vec![shape("الإجمالي", Script::Arabic), data.total_str.clone()],
+        ];
+        // Box edges follow the ink of the first and last rows (the order type is payload text)
+        let top = y + ink_extent(&label_font, s_sum, &rows[0]).0 - pad;
+        let (last_y, last_bottom) = match &data.order_type {
+            Some(order_type) => (
+                y + 2 * line_h,
+                ink_extent(&label_font, s_sum, &[shape("نوع الطلب", Script::Arabic)]).1
+                    .max(ink_extent(&font, s_sum, &[shape(order_type, script)]).1),
+            ),
+            None => (y + line_h, ink_extent(&label_font, s_sum, &rows[1]).1),
+        };
+        let bottom = last_y + last_bottom + pad;
 
-    let r_name  = right_edge;
-    let r_qty   = r_name  - w_name;
-    let r_price = r_qty   - w_qty;
-    let r_total = r_price - w_total;
+        let right = right_edge - inset;
+        draw_total_row(&mut img, &label_font, s_sum, "عدد الأصناف", &count, right, y);
+        y += line_h;
+        draw_total_row(&mut img, &label_font, s_sum, "الإجمالي", &data.total_str, right, y);
+        if let Some(order_type) = &data.order_type {
+            y += line_h;
+            let label = "نوع الطلب";
+            let lw = shaped_width(s_sum, &label_font, Script::Arabic, label);
+            draw_mixed_rtl_right(&mut img, &font, script, s_sum, order_type, right - lw - 12, y);
+            draw_mixed_rtl_right(&mut img, &label_font, Script::Arabic, s_sum, label, right, y);
+        }
+        draw_box(&mut img, margin_h + inset / 2, top, right_edge - inset / 2, bottom, 2);
+
+        // The headings' ink starts one padding below the box
+        let headings: Vec<String> = ["الصنف", "الكمية", "السعر", "القيمة"].iter().map(|h| shape(h, Script::Arabic)).collect();
+        y = bottom + pad - ink_extent(&label_font, s_sum, &headings).0;
+    }
+
+    // Columns (a fifth, running-balance column narrows the others when enabled)
+    let cols: &[f32] = if layout.running_balance { &layout.cols_running } else { &layout.cols };
+    let Columns { w_name, r_name, r_qty, r_price, r_total, r_balance } = columns(cols, inner_w, right_edge);
 
     // Headings
     let s_head = Scale::uniform(layout.fonts.header_cols);
-    draw_mixed_rtl_right(&mut img, &font, s_head, "الصنف",  r_name,  y);
-    draw_mixed_rtl_right(&mut img, &font, s_head, "الكمية", r_qty,   y);
-    draw_mixed_rtl_right(&mut img, &font, s_head, "السعر",  r_price, y);
-    draw_mixed_rtl_right(&mut img, &font, s_head, "القيمة", r_total, y);
+    draw_mixed_rtl_right(&mut img, &label_font, Script::Arabic, s_head, "الصنف",  r_name,  y);
+    draw_mixed_rtl_right(&mut img, &label_font, Script::Arabic, s_head, "الكمية", r_qty,   y);
+    draw_mixed_rtl_right(&mut img, &label_font, Script::Arabic, s_head, "السعر",  r_price, y);
+    draw_mixed_rtl_right(&mut img, &label_font, Script::Arabic, s_head, "القيمة", r_total, y);
+    if layout.running_balance {
+        draw_mixed_rtl_right(&mut img, &label_font, Script::Arabic, s_head, "الرصيد", r_balance, y);
+    }
     y += layout.row_gap - 6;
 
     // Rows with wrapping (max 2 lines for name)
     let s_item = Scale::uniform(layout.fonts.item);
     let s_discount = Scale::uniform(layout.fonts.item * 0.82);
-    for it in &data.items {
-        let lines = wrap_mixed_rtl(&font, s_item, &it.name, w_name).into_iter().take(2).collect::<Vec<_>>();
+    let s_time = Scale::uniform(layout.fonts.item_time);
+    let s_component = Scale::uniform(layout.fonts.item * 0.82);
+    let component_indent = 28;
+    let time_pad = 4;
+    let time_gap = 8;
+    let thumb_gap = 8;
+    let thumb_pad = 4;
+    for (it, balance) in data.items.iter().zip(running_totals(&data.items)) {
+        // Thumbnail sits at the right edge (start of the RTL row) and pushes the name left
+        let thumb_w = it.thumbnail.as_ref().map(|t| t.width() as i32 + thumb_gap).unwrap_or(0);
+        let r_item_name = r_name - thumb_w;
+        // Order time sits at the left edge of the name column; the name wraps in the remaining width
+        let time_w = it.time.as_deref().map(|t| time_pad + measure(s_time, &font, t) + time_gap).unwrap_or(0);
+        let lines = wrap_mixed_rtl(&font, script, s_item, &it.name, w_name - time_w - thumb_w).into_iter().take(2).collect::<Vec<_>>();
         let line_count = lines.len().max(1);
 
+        // Top-aligned with the ink of the name's first line
+        let thumb_top = match &it.thumbnail {
+            Some(thumb) => {
+                let first_line: Vec<String> = lines.first().map(|ln| shape(ln, script)).into_iter().collect();
+                let top = ink_extent(&font, s_item, &first_line).0;
+                let x = (r_name - thumb.width() as i32) as i64;
+                imageops::overlay(&mut img, thumb, x, (y + top) as i64);
+                top
+            }
+            None => 0,
+        };
+
         for (i, ln) in lines.iter().enumerate() {
             let yy = y + (i as i32) * (layout.row_gap - 4);
 
-            draw_mixed_rtl_right(&mut img, &font, s_item, ln, r_name, yy);
+            draw_mixed_rtl_right(&mut img, &font, script, s_item, ln, r_item_name, yy);
 
             if i == 0 {
+                if let Some(t) = &it.time {
+                    let dy = (s_item.y - s_time.y) as i32 / 2;
+                    draw_crisp(&mut img, t, r_qty + time_pad, yy + dy, s_time, &font);
+                }
                 draw_ltr_right(&mut img, &font, s_item, &it.qty_str, r_qty, yy);
                 draw_ltr_right(&mut img, &font, s_item, &it.price_str, r_price, yy);
                 draw_ltr_right(&mut img, &font, s_item, &it.total_str, r_total, yy);
+                if layout.running_balance {
+                    draw_ltr_right(&mut img, &font, s_item, &format!("{:.2}", balance), r_balance, yy);
+                }
             }
         }
 
-        y += (line_count as i32) * (layout.row_gap - 4);
+        let text_h = (line_count as i32) * (layout.row_gap - 4);
+        let thumb_h = it.thumbnail.as_ref().map(|t| thumb_top + t.height() as i32 + thumb_pad).unwrap_or(0);
+        y += text_h.max(thumb_h);
+
+        // Combo sub-components: indented, smaller, no numeric columns; each wraps within the name column
+        let component_lines: Vec<String> = it.components.iter()
+            .flat_map(|c| wrap_mixed_rtl(&font, script, s_component, c, w_name - component_indent))
+            .collect();
+        for ln in &component_lines {
+            draw_mixed_rtl_right(&mut img, &font, script, s_component, ln, r_name - component_indent, y);
+            y += layout.row_gap - 4;
+        }
 
         if let Some(op) = it.original_price {
             if op > it.price + 0.001 {
@@ -354,14 +690,14 @@ fn render_receipt(data: &ReceiptData, layout: &Layout) -> GrayImage {
                         for dy in 0..3u32 {
                             let sy = strike_y + dy;
                             if sy < img.height() {
-                                img.put_pixel(sx, sy, Rgb([0,0,0]));
+                                img.put_pixel(sx, sy, Luma([0]));
                             }
                         }
                     }
                 }
 
                 let discount_label = format!("خصم {:.2}", saved);
-                draw_mixed_rtl_right(&mut img, &font, s_discount, &discount_label, r_name, y);
+                draw_mixed_rtl_right(&mut img, &label_font, Script::Arabic, s_discount, &discount_label, r_name, y);
 
                 y += layout.row_gap - 4;
             }
@@ -373,33 +709,41 @@ fn render_receipt(data: &ReceiptData, layout: &Layout) -> GrayImage {
     draw_dotted(&mut img, y, margin_h, paper_w - margin_h);
     y += 12;
 
-    // Discount (optional)
-    if data.discount > 0.0001 {
-        let gap = 12;
-        let label = "الخصم";
-        let lw = measure(Scale::uniform(layout.fonts.total_label), &font, &shape(label));
-        let right = right_edge;
-        draw_ltr_right(&mut img, &font, Scale::uniform(layout.fonts.total_label),
-                       &data.discount_str, right - lw - gap, y);
-        draw_mixed_rtl_right(&mut img, &font, Scale::uniform(layout.fonts.total_label), label, right, y);
+    // Discount and fees (optional, skipped when zero)
+    let extra_rows = [
+        ("الخصم", data.discount, &data.discount_str),
+        ("رسوم الخدمة", data.service_fee, &data.service_fee_str),
+        ("رسوم التوصيل", data.delivery_fee, &data.delivery_fee_str),
+    ];
+    for (label, amount, amount_str) in extra_rows {
+        if amount > 0.0001 {
+            draw_total_row(&mut img, &label_font, Scale::uniform(layout.fonts.total_label), label, amount_str, right_edge, y);
+            y += layout.row_gap - 6;
+        }
+    }
+
+    // Cash rounding adjustment (signed), when the grand total was rounded
+    if let Some(adjustment) = &data.rounding_adjustment {
+        draw_total_row(&mut img, &label_font, Scale::uniform(layout.fonts.total_label), "تقريب", adjustment, right_edge, y);
         y += layout.row_gap - 6;
     }
 
-    // Grand total
+    // Grand total: the larger value is vertically centered on the label
     let gap = 12;
     let label = "إجمالي الفاتورة";
-    let lw = measure(Scale::uniform(layout.fonts.total_label), &font, &shape(label));
+    let lw = shaped_width(Scale::uniform(layout.fonts.total_label), &label_font, Script::Arabic, label);
     let right = right_edge;
-    draw_ltr_right(&mut img, &font, Scale::uniform(layout.fonts.total_value),
-                   &data.total_str, right - lw - gap, y - 10);
-    draw_mixed_rtl_right(&mut img, &font, Scale::uniform(layout.fonts.total_label), label, right, y);
+    let value_dy = center_offset(&label_font, Scale::uniform(layout.fonts.total_label), Scale::uniform(layout.fonts.total_value));
+    draw_ltr_right(&mut img, &label_font, Scale::uniform(layout.fonts.total_value),
+                   &data.total_str, right - lw - gap, y + value_dy);
+    draw_mixed_rtl_right(&mut img, &label_font, Script::Arabic, Scale::uniform(layout.fonts.total_label), label, right, y);
     y += layout.row_gap;
 
     // Footer
-    draw_mixed_rtl_center(&mut img, &font, Scale::uniform(layout.fonts.footer), &data.footer_address,  paper_w, y);
+    draw_mixed_rtl_center(&mut img, &font, script, Scale::uniform(layout.fonts.footer), &data.footer_address,  paper_w, y);
     y += layout.fonts.footer as i32 + 2;
 
-    draw_mixed_rtl_center(&mut img, &font, Scale::uniform(layout.fonts.footer), &data.footer_delivery, paper_w, y);
+    draw_mixed_rtl_center(&mut img, &font, script, Scale::uniform(layout.fonts.footer), &data.footer_delivery, paper_w, y);
     y += layout.fonts.footer as i32 + 2;
 
     if !data.footer_phones.is_empty() {
@@ -409,11 +753,110 @@ fn render_receipt(data: &ReceiptData, layout: &Layout) -> GrayImage {
 
     y += layout.margin_bottom;
 
-    // Crop & grayscale
+    // Crop
     let used_h = (y as u32).min(2498);
-    DynamicImage::ImageRgb8(img)
-        .crop_imm(0, 0, layout.paper_width_px, used_h)
-        .to_luma8()
+    let mut gray = imageops::crop_imm(&img, 0, 0, layout.paper_width_px, used_h).to_image();
+
+    // Optionally cut right below the last inked row (+ auto_trim_margin) instead of at the computed y
+    if layout.auto_trim_bottom {
+        let keep = last_content_row(&gray)
+            .map(|row| row + 1 + layout.auto_trim_margin)
+            .unwrap_or(0)
+            .min(gray.height());
+        gray = imageops::crop_imm(&gray, 0, 0, gray.width(), keep).to_image();
+    }
+
+    if let Some(wm) = &data.watermark {
+        apply_watermark(&mut gray, wm);
+    }
+    gray
+}
+
+// Running-balance column: the sum of line totals through each item
+fn running_totals(items: &[Item]) -> Vec<f64> {
+    items.iter()
+        .scan(0.0f64, |sum, it| { *sum += it.total; Some(*sum) })
+        .collect()
+}
+
+// Lowest row holding any non-white pixel
+fn last_content_row(gray: &GrayImage) -> Option<u32> {
+    (0..gray.height()).rev().find(|&y| (0..gray.width()).any(|x| gray.get_pixel(x, y)[0] != 255))
+}
+
+// ===================== Images =====================
+// Decoded images are capped in pixels as well as bytes: a small PNG can still expand to a huge bitmap.
+const IMAGE_MAX_PIXELS: u64 = 2048 * 2048;
+
+// Decode PNG/JPEG/... bytes to grayscale, scaled (aspect kept) to fit `max_w` x `max_h`.
+// Transparency is flattened onto white paper first; transparent PNG pixels are usually stored as
+// (0,0,0,0) and would otherwise come out black.
+fn decode_gray(what: &str, bytes: &[u8], max_bytes: usize, max_w: u32, max_h: u32) -> Result<GrayImage> {
+    if bytes.len() > max_bytes {
+        return Err(Error::new(Status::InvalidArg, format!(
+            "{} is {} bytes (max {})", what, bytes.len(), max_bytes)));
+    }
+    let decode_err = |e: image::ImageError| Error::new(Status::InvalidArg, format!("decode {}: {}", what, e));
+    let (w, h) = image::io::Reader::new(std::io::Cursor::new(bytes))
+        .with_guessed_format()
+        .map_err(|e| Error::new(Status::InvalidArg, format!("decode {}: {}", what, e)))?
+        .into_dimensions()
+        .map_err(decode_err)?;
+    if w as u64 * h as u64 > IMAGE_MAX_PIXELS {
+        return Err(Error::new(Status::InvalidArg, format!(
+            "{} is {}x{} px (max {} px)", what, w, h, IMAGE_MAX_PIXELS)));
+    }
+
+    let mut rgba = image::load_from_memory(bytes).map_err(decode_err)?.to_rgba8();
+    for px in rgba.pixels_mut() {
+        let a = px[3] as u32;
+        for c in px.0.iter_mut().take(3) {
+            *c = ((*c as u32 * a + 255 * (255 - a) + 127) / 255) as u8;
+        }
+        px[3] = 255;
+    }
+    let flat = image::DynamicImage::ImageRgba8(rgba).to_luma8();
+    Ok(image::DynamicImage::ImageLuma8(flat).resize(max_w, max_h, FilterType::Triangle).to_luma8())
+}
+
+// Per-item thumbnails: encoded size cap and box side in dots (two item lines tall).
+const THUMB_MAX_BYTES: usize = 256 * 1024;
+const THUMB_MAX_PX: u32 = 56;
+
+// Background watermark (store logo): encoded size cap.
+const WATERMARK_MAX_BYTES: usize = 1024 * 1024;
+
+// Thumbnails are photos, so they get error-diffusion dithering rather than the raster threshold.
+fn decode_thumbnail(bytes: &[u8]) -> Result<GrayImage> {
+    let mut thumb = decode_gray("item image", bytes, THUMB_MAX_BYTES, THUMB_MAX_PX, THUMB_MAX_PX)?;
+    imageops::dither(&mut thumb, &imageops::BiLevel);
+    Ok(thumb)
+}
+
+// 4x4 Bayer matrix for ordered dithering
+const BAYER_4X4: [[u8; 4]; 4] = [
+    [0, 8, 2, 10],
+    [12, 4, 14, 6],
+    [3, 11, 1, 9],
+    [15, 7, 13, 5],
+];
+
+// Faint background image: ordered-dithered at `opacity` and only placed on pure-white
+// pixels, so it sits behind the text and never eats into glyphs.
+fn apply_watermark(gray: &mut GrayImage, wm: &Watermark) {
+    let (ww, wh) = wm.image.dimensions();
+    if ww == 0 || wh == 0 { return; }
+    for y in 0..gray.height() {
+        for x in 0..gray.width().min(ww) {
+            if gray.get_pixel(x, y)[0] != 255 { continue; }
+            let Luma([v]) = *wm.image.get_pixel(x, y % wh);
+            let darkness = (255 - v) as f32 / 255.0 * wm.opacity;
+            let level = (BAYER_4X4[(y % 4) as usize][(x % 4) as usize] as f32 + 0.5) / 16.0;
+            if darkness > level {
+                gray.put_pixel(x, y, Luma([0]));
+            }
+        }
+    }
 }
 
 // ===================== ESC * 24 band pack =====================
@@ -437,108 +880,1456 @@ fn pack_esc_star_24(gray: &GrayImage, y0: u32, threshold: u8) -> Vec<u8> {
     band
 }
 
-// ===================== N-API entry =====================
-#[napi(js_name = "printReceipt")]
-pub async fn print_receipt(payload: JsPrintPayload) -> Result<String> {
+// ===================== Code 128 barcode =====================
+// Centered Code 128 (subset B) with the HRI caption printed below; alignment is reset to left afterwards.
+fn code128_bytes(data: &str) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+
+    // Align Center
+    out.extend_from_slice(&[0x1B, 0x61, 0x01]);
+
+    // Barcode Height: 40 dots
+    out.extend_from_slice(&[0x1D, 0x68, 40]);
+    // Barcode Width Multiplier: 2 (compact width)
+    out.extend_from_slice(&[0x1D, 0x77, 2]);
+    // HRI Character Print Position: Below Barcode
+    out.extend_from_slice(&[0x1D, 0x48, 0x02]);
+
+    // Print Barcode: GS k <type: 73 for Code 128> <length> <data>
+    // Note: Code 128 requires subset character at start (e.g. {B for Subset B)
+    // {B in ASCII is `{` (123) and `B` (66)
+    let mut payload_bytes = Vec::new();
+    payload_bytes.push(123); // {
+    payload_bytes.push(66);  // B
+    // A literal `{` in the data would start a code-set escape; it is sent as `{{`
+    for b in data.bytes() {
+        if b == b'{' { payload_bytes.push(b'{'); }
+        payload_bytes.push(b);
+    }
+
+    // Barcode system expects the payload length for type 73; cutting the data would change what
+    // scans (and could split a `{{`), so an oversized payload is an error
+    if payload_bytes.len() > 255 {
+        return Err(Error::new(Status::InvalidArg, format!(
+            "barcode data \"{}\" is {} bytes escaped (max 255)", data, payload_bytes.len())));
+    }
+    out.extend_from_slice(&[0x1D, 0x6B, 73]);
+    out.push(payload_bytes.len() as u8);
+    out.extend(payload_bytes);
+
+    // Reset Alignment to Left
+    out.extend_from_slice(&[0x1B, 0x61, 0x00]);
+    Ok(out)
+}
+
+// Amounts are printed with two decimals when they parse; otherwise the caller's string is kept as-is.
+fn format_amount(s: &str) -> String {
+    match parse_number(s) {
+        Some(v) => format!("{:.2}", v),
+        None => s.trim().to_string(),
+    }
+}
+
+// Invoice number as Code 128 subset B can carry it: ASCII letters/digits (Arabic-Indic digits
+// converted) and the separators - / . #, so "فاتورة رقم: 42" becomes "42".
+fn barcode_number(number: &str) -> String {
+    let kept: String = number.chars()
+        .filter_map(|c| match c {
+            '\u{0660}'..='\u{0669}' => Some(char::from(b'0' + (c as u32 - 0x0660) as u8)),
+            '\u{06F0}'..='\u{06F9}' => Some(char::from(b'0' + (c as u32 - 0x06F0) as u8)),
+            c if c.is_ascii_alphanumeric() || matches!(c, '-' | '/' | '.' | '#') => Some(c),
+            _ => None,
+        })
+        .collect();
+    kept.trim_matches(|c: char| !c.is_ascii_alphanumeric()).to_string()
+}
+
+// Widest subset B payload that fits 576 dots at GS w 2: each character is an 11-module symbol
+// (22 dots) and start + check + stop take 35 modules (70 dots), so (576 - 70) / 22 = 23.
+const RECONCILE_MAX_CHARS: usize = 23;
+
+// Payload for the reconciliation barcode, e.g. "123456|97.20". Subset B only encodes printable
+// ASCII, so anything else is rejected rather than sent as raw UTF-8.
+fn reconcile_payload(number: &str, total: &str, delimiter: &str) -> Result<String> {
+    let number = barcode_number(number);
+    if number.is_empty() {
+        return Err(Error::new(Status::InvalidArg, "reconcileBarcode: number has no letters or digits to encode".to_string()));
+    }
+    if !delimiter.chars().all(|c| c.is_ascii_graphic() || c == ' ') {
+        return Err(Error::new(Status::InvalidArg, format!("reconcileDelimiter \"{}\" must be printable ASCII", delimiter)));
+    }
+    let total = format_amount(total);
+    if !total.chars().all(|c| c.is_ascii_graphic()) {
+        return Err(Error::new(Status::InvalidArg, format!("reconcileBarcode: total \"{}\" is not a number", total)));
+    }
+    let encoded = format!("{}{}{}", number, delimiter, total);
+    if encoded.len() > RECONCILE_MAX_CHARS {
+        return Err(Error::new(Status::InvalidArg, format!(
+            "reconcileBarcode: \"{}\" is {} characters; at most {} fit across the paper", encoded, encoded.len(), RECONCILE_MAX_CHARS)));
+    }
+    Ok(encoded)
+}
+
+// ===================== Amounts =====================
+// Largest difference between the supplied and computed total still accepted under "error".
+const TOTAL_TOLERANCE: f64 = 0.01;
+
+// Numeric strings as POS frontends send them: ASCII or Arabic-Indic digits, "٫" or "." as the
+// decimal point, "," / "٬" as thousands separators. Parsed as f64: f32 sums drift off the cent above ~65536.
+fn parse_number(s: &str) -> Option<f64> {
+    let normalized: String = s.trim().chars()
+        .filter(|c| !matches!(c, ',' | '\u{066C}'))
+        .map(|c| match c {
+            '\u{0660}'..='\u{0669}' => char::from(b'0' + (c as u32 - 0x0660) as u8),
+            '\u{06F0}'..='\u{06F9}' => char::from(b'0' + (c as u32 - 0x06F0) as u8),
+            '\u{066B}' => '.',
+            _ => c,
+        })
+        .collect();
+    normalized.parse::<f64>().ok()
+}
+
+// Sum of line totals minus the invoice-level discount, plus service/delivery fees.
+fn expected_total(items: &[Item], discount: f64, fees: f64) -> f64 {
+    items.iter().map(|i| i.total).sum::<f64>() - discount + fees
+}
+
+// Rounds to the nearest `step` (e.g. 0.05), returning (rounded, rounded - exact). None when the
+// step is not a positive finite number or the total is already on a step.
+fn round_cash(exact: f64, step: f64) -> Option<(f64, f64)> {
+    if !step.is_finite() || step <= 0.0 { return None; }
+    let rounded = (exact / step).round() * step;
+    let adjustment = rounded - exact;
+    if adjustment.abs() < 0.005 { return None; }
+    Some((rounded, adjustment))
+}
+
+// ===================== Print jobs =====================
+// How a job is finished once its raster and barcodes have been sent.
+#[derive(Clone, Copy, PartialEq)]
+enum Finisher {
+    Cut,       // feed past the blade, then cut (receipts)
+    Feed,      // feed past the blade, no cut
+    LabelFeed, // GS FF: advance to the next label's print start
+}
+
+#[derive(Clone)]
+struct PrintJob {
+    data: ReceiptData,
+    layout: Layout,
+    finisher: Finisher,
+}
+
+// Receipts are cut unless `cut: false`; labels are label-fed unless `cut: true`.
+fn finisher_for(media: Option<&str>, cut: Option<bool>) -> Result<Finisher> {
+    match media.unwrap_or("receipt") {
+        "receipt" => Ok(if cut.unwrap_or(true) { Finisher::Cut } else { Finisher::Feed }),
+        "label" => Ok(if cut.unwrap_or(false) { Finisher::Cut } else { Finisher::LabelFeed }),
+        other => Err(Error::new(Status::InvalidArg, format!("unknown media \"{}\" (expected \"receipt\" or \"label\")", other))),
+    }
+}
+
+fn job_from_payload(payload: JsPrintPayload) -> Result<PrintJob> {
+    let finisher = finisher_for(payload.media.as_deref(), payload.cut)?;
+
     // Convert payload to internal structs
     let items: Vec<Item> = payload.items.into_iter()
-        .map(|i| {
-            let price = i.price.parse::<f32>().unwrap_or(0.0);
-            let original_price = i.originalPrice.as_ref().and_then(|s| s.parse::<f32>().ok());
-            Item { name: i.name, qty_str: i.qty, price, original_price, price_str: i.price, total_str: i.total, original_price_str: i.originalPrice }
+        .map(|i| -> Result<Item> {
+            let price = parse_number(&i.price).unwrap_or(0.0) as f32;
+            let original_price = i.originalPrice.as_deref().and_then(parse_number).map(|p| p as f32);
+            let total = parse_number(&i.total).unwrap_or(0.0);
+            let components = i.components.unwrap_or_default().into_iter().filter(|c| !c.trim().is_empty()).collect();
+            let thumbnail = match &i.image {
+                Some(bytes) => Some(decode_thumbnail(bytes)?),
+                None => None,
+            };
+            Ok(Item {
+                name: i.name, qty_str: i.qty, price, original_price, price_str: i.price, total, total_str: i.total, original_price_str: i.originalPrice,
+                time: i.time.filter(|t| !t.trim().is_empty()),
+                components,
+                thumbnail,
+            })
         })
-        .collect();
+        .collect::<Result<_>>()?;
+
+    let discount = payload.discount.as_deref().and_then(parse_number).unwrap_or(0.0);
+    let service_fee = payload.serviceFee.as_deref().and_then(parse_number).unwrap_or(0.0);
+    let delivery_fee = payload.deliveryFee.as_deref().and_then(parse_number).unwrap_or(0.0);
+    let fees = service_fee + delivery_fee;
+    let policy = payload.totalMismatchPolicy.as_deref().unwrap_or("trust");
+    if matches!(policy, "recompute" | "error") {
+        // Amounts that don't parse would silently count as 0 in the sum
+        if let Some(it) = items.iter().find(|i| parse_number(&i.total_str).is_none()) {
+            return Err(Error::new(Status::InvalidArg, format!(
+                "invalid payload: item \"{}\" total \"{}\" is not a number", it.name, it.total_str)));
+        }
+        for (field, raw) in [("discount", &payload.discount), ("serviceFee", &payload.serviceFee), ("deliveryFee", &payload.deliveryFee)] {
+            if let Some(raw) = raw.as_deref().filter(|r| !r.trim().is_empty()) {
+                if parse_number(raw).is_none() {
+                    return Err(Error::new(Status::InvalidArg, format!(
+                        "invalid payload: {} \"{}\" is not a number", field, raw)));
+                }
+            }
+        }
+    }
+    let total_str = match policy {
+        "trust" => payload.total,
+        "recompute" => format!("{:.2}", expected_total(&items, discount, fees)),
+        "error" => {
+            let expected = expected_total(&items, discount, fees);
+            match parse_number(&payload.total) {
+                Some(t) if (t - expected).abs() <= TOTAL_TOLERANCE => payload.total,
+                _ => return Err(Error::new(Status::InvalidArg, format!(
+                    "invalid payload: total \"{}\" does not match items - discount + fees ({:.2})", payload.total, expected))),
+            }
+        }
+        other => return Err(Error::new(Status::InvalidArg, format!(
+            "unknown totalMismatchPolicy \"{}\" (expected \"trust\", \"recompute\" or \"error\")", other))),
+    };
+
+    let reconcile_barcode = if payload.reconcileBarcode.unwrap_or(false) {
+        let delimiter = payload.reconcileDelimiter.as_deref().unwrap_or("|");
+        Some(reconcile_payload(&payload.number, &total_str, delimiter)?)
+    } else {
+        None
+    };
+
+    // Cash rounding: the reconciliation barcode above keeps the exact total, the receipt shows
+    // the rounded one plus an adjustment row.
+    let mut total_str = total_str;
+    let mut rounding_adjustment = None;
+    if let Some(step) = payload.cashRounding {
+        if !step.is_finite() || step <= 0.0 {
+            return Err(Error::new(Status::InvalidArg, format!(
+                "cashRounding must be a positive number (got {})", step)));
+        }
+    }
+    if let (Some(step), Some(exact)) = (payload.cashRounding, parse_number(&total_str)) {
+        if let Some((rounded, adjustment)) = round_cash(exact, step) {
+            total_str = format!("{:.2}", rounded);
+            rounding_adjustment = Some(format!("{:.2}", adjustment));
+        }
+    }
+
+    let script = match payload.script.as_deref().unwrap_or("arabic") {
+        "arabic" => Script::Arabic,
+        "hebrew" => Script::Hebrew,
+        "none" => Script::None,
+        other => return Err(Error::new(Status::InvalidArg, format!(
+            "unknown script \"{}\" (expected \"arabic\", \"hebrew\" or \"none\")", other))),
+    };
+    let font = match &payload.fontBytes {
+        Some(bytes) => Some(Font::try_from_vec(bytes.to_vec())
+            .ok_or_else(|| Error::new(Status::InvalidArg, "fontBytes is not a valid TrueType/OpenType font".to_string()))?),
+        None => None,
+    };
+    let layout = Layout {
+        script,
+        font,
+        running_balance: payload.runningBalance.unwrap_or(false),
+        auto_trim_bottom: payload.autoTrimBottom.unwrap_or(false),
+        auto_trim_margin: payload.autoTrimMargin.unwrap_or(0),
+        ..Layout::default()
+    };
+
+    let watermark = match &payload.watermark {
+        Some(wm) => Some(Watermark {
+            image: decode_gray("watermark", &wm.bytes, WATERMARK_MAX_BYTES, layout.paper_width_px, CANVAS_HEIGHT)?,
+            opacity: wm.opacity.unwrap_or(0.15).clamp(0.0, 1.0) as f32,
+        }),
+        None => None,
+    };
 
     let data = ReceiptData {
         store_name: payload.title,
         date_time_line: payload.time,
         invoice_no: payload.number,
         items,
-        discount: payload.discount.as_ref().and_then(|s| s.parse::<f32>().ok()).unwrap_or(0.0),
+        discount,
         discount_str: payload.discount.unwrap_or_default(),
-        total_str: payload.total,
+        service_fee,
+        service_fee_str: payload.serviceFee.unwrap_or_default(),
+        delivery_fee,
+        delivery_fee_str: payload.deliveryFee.unwrap_or_default(),
+        total_str,
+        rounding_adjustment,
         footer_address: payload.footer.address,
         footer_delivery: payload.footer.lastLine,
         footer_phones: payload.footer.phones.unwrap_or_default(),
         uuid: payload.uuid,
+        reconcile_barcode,
+        watermark,
+        end_marker: payload.endMarker.filter(|m| !m.trim().is_empty()),
+        summary_header: payload.summaryHeader.unwrap_or(false),
+        order_type: payload.orderType.filter(|t| !t.trim().is_empty()),
+    };
+
+    Ok(PrintJob { data, layout, finisher })
+}
+
+fn send_raster<D: Driver>(tx: &mut Tx<D>, gray: &GrayImage, threshold: u8) -> Result<()> {
+    // ESC * 24-dot double density
+    let w = gray.width();
+    let n = w as u16;
+    let nL = (n & 0xFF) as u8;
+    let nH = ((n >> 8) & 0xFF) as u8;
+
+    let mut y0 = 0u32;
+    while y0 < gray.height() {
+        let band = pack_esc_star_24(gray, y0, threshold);
+        tx.custom("ESC * band", &[0x1B, 0x2A, 33, nL, nH])?;
+        tx.custom("band data", &band)?;
+        tx.custom("LF", &[0x0A])?;
+        y0 += 24;
+    }
+    Ok(())
+}
+
+// Raster, barcodes and finisher for one job. Each job is flushed on its own so a batch
+// shares the connection but every item gets its own cut/label-feed.
+fn send_job<D: Driver>(tx: &mut Tx<D>, job: &PrintJob) -> Result<()> {
+    let data = &job.data;
+    let layout = &job.layout;
+    // Barcodes are built first so a payload that can't be encoded fails before anything prints
+    let uuid_barcode = data.uuid.as_deref().map(code128_bytes).transpose()?;
+    let reconcile_barcode = data.reconcile_barcode.as_deref().map(code128_bytes).transpose()?;
+    let gray = render_receipt(data, layout);
+    send_raster(tx, &gray, layout.threshold)?;
+
+    // Print optional UUID as a 1D Barcode (Code 128)
+    if let Some(barcode) = &uuid_barcode {
+        tx.custom("barcode margin", &[0x0A, 0x0A])?;
+        tx.custom("uuid barcode", barcode)?;
+    }
+
+    // Reconciliation barcode: "<number><delimiter><total>" with the caption printed below
+    if let Some(barcode) = &reconcile_barcode {
+        tx.custom("barcode margin", &[0x0A, 0x0A])?;
+        tx.custom("reconcile barcode", barcode)?;
+    }
+
+    if let Some(marker) = &data.end_marker {
+        send_raster(tx, &render_end_marker(marker, layout), layout.threshold)?;
+    }
+
+    match job.finisher {
+        Finisher::Cut => {
+            // Feed 3 lines before cutting so the blade clears the last printed content
+            tx.custom("feed", &[0x0A, 0x0A, 0x0A])?;
+            tx.print(true)?;
+        }
+        Finisher::Feed => {
+            tx.custom("feed", &[0x0A, 0x0A, 0x0A])?;
+            tx.print(false)?;
+        }
+        Finisher::LabelFeed => {
+            tx.custom("label feed (GS FF)", &[0x1D, 0x0C])?;
+            tx.print(false)?;
+        }
+    }
+    Ok(())
+}
+
+// ===================== Transmission =====================
+// Every command goes through here so `debug` can dump the exact stream to the configured sink.
+struct Tx<'a, D: Driver> {
+    p: &'a mut Printer<D>,
+    log: Option<Box<dyn Write>>,
+}
+
+impl<'a, D: Driver> Tx<'a, D> {
+    fn custom(&mut self, what: &str, bytes: &[u8]) -> Result<()> {
+        self.dump(what, bytes);
+        self.p.custom(bytes).map_err(|e| Error::from_reason(e.to_string()))?;
+        Ok(())
+    }
+
+    // Flush everything queued so far, optionally ending with a full cut (GS V 0).
+    fn print(&mut self, cut: bool) -> Result<()> {
+        if cut {
+            self.custom("cut (GS V 0)", &[0x1D, 0x56, 0x00])?;
+        }
+        self.p.print().map_err(|e| Error::from_reason(e.to_string()))?;
+        Ok(())
+    }
+
+    // Hex dump, 32 bytes per line with offsets. Log failures never fail the print.
+    fn dump(&mut self, what: &str, bytes: &[u8]) {
+        let Some(log) = self.log.as_mut() else { return };
+        let _ = writeln!(log, "[pos-receipt-printer] {} ({} bytes)", what, bytes.len());
+        for (i, chunk) in bytes.chunks(32).enumerate() {
+            let hex: Vec<String> = chunk.iter().map(|b| format!("{:02X}", b)).collect();
+            let _ = writeln!(log, "  {:06X}  {}", i * 32, hex.join(" "));
+        }
+        let _ = log.flush();
+    }
+}
+
+// Where debug dumps go
+#[derive(Clone)]
+enum DebugLog {
+    Stderr,
+    File(PathBuf), // appended to
+}
+
+// ===================== Printer profiles =====================
+// Model-specific setup sent right after ESC @ (init).
+// ESC t 0: PC437 code page, ESC SP 0: no extra character spacing, ESC 3 24: line feed = one raster band.
+const PROFILE_EPSON_TM: &[u8] = &[0x1B, 0x74, 0x00, 0x1B, 0x20, 0x00, 0x1B, 0x33, 24];
+// Xprinter firmware boots in Chinese (double-byte) mode on many units; FS . leaves it first.
+const PROFILE_XPRINTER: &[u8] = &[0x1C, 0x2E, 0x1B, 0x74, 0x00, 0x1B, 0x20, 0x00, 0x1B, 0x33, 24];
+
+fn init_profile(payload: &JsPrintPayload) -> Result<Vec<u8>> {
+    match payload.printerProfile.as_deref().unwrap_or("generic") {
+        "generic" => Ok(Vec::new()),
+        "epson-tm" => Ok(PROFILE_EPSON_TM.to_vec()),
+        "xprinter" => Ok(PROFILE_XPRINTER.to_vec()),
+        "custom" => payload.initBytes.as_ref()
+            .map(|b| b.to_vec())
+            .ok_or_else(|| Error::new(Status::InvalidArg, "printerProfile \"custom\" requires initBytes".to_string())),
+        other => Err(Error::new(Status::InvalidArg, format!(
+            "unknown printerProfile \"{}\" (expected \"generic\", \"epson-tm\", \"xprinter\" or \"custom\")", other))),
+    }
+}
+
+// ===================== Connection =====================
+// Per-connection settings; in a batch they come from the first payload.
+struct Connection {
+    port: String,
+    baud: u32,
+    write_timeout: Option<Duration>,
+    init_bytes: Vec<u8>,
+    spool_dir: Option<PathBuf>, // set when spoolOnFailure is on
+    debug_log: Option<DebugLog>, // set when debug is on
+}
+
+// What happened to a print request.
+enum Outcome {
+    Printed,
+    Spooled(PathBuf),
+}
+
+fn connection_from_payload(payload: &JsPrintPayload) -> Result<Connection> {
+    Ok(Connection {
+        port: env_port_or_default(payload.port.clone()),
+        baud: env_baud_or_default(payload.baud),
+        write_timeout: payload.writeTimeoutMs.map(|ms| Duration::from_millis(ms as u64)),
+        init_bytes: init_profile(payload)?,
+        spool_dir: if payload.spoolOnFailure.unwrap_or(false) {
+            Some(payload.spoolDir.as_ref().map(PathBuf::from)
+                .unwrap_or_else(|| std::env::temp_dir().join("pos-receipt-printer-spool")))
+        } else {
+            None
+        },
+        debug_log: if payload.debug.unwrap_or(false) {
+            Some(payload.debugLog.as_ref().map(|p| DebugLog::File(PathBuf::from(p))).unwrap_or(DebugLog::Stderr))
+        } else {
+            None
+        },
+    })
+}
+
+// Opens the port once and sends every job over it (blocking). If the port cannot be opened and
+// spooling is on, the exact byte stream goes to `<spoolDir>/<port>/<unix-ms>.bin` instead, for a
+// separate process to replay once the printer is back.
+//
+// Short writes: escpos' `Driver::write` returns `()`, not a byte count, so we cannot see how much
+// of a band the OS accepted. `SerialPortDriver` writes with `write_all`, which already loops over
+// short writes; what remains is a port that stays saturated until the write timeout expires. That
+// surfaces as an error from `print()` (never as a silently truncated band) -- raise
+// `writeTimeoutMs` for slow links instead of relying on the serialport default.
+fn run_jobs(conn: &Connection, jobs: &[PrintJob]) -> Result<Outcome> {
+    let open_err = match SerialPortDriver::open(&conn.port, conn.baud, conn.write_timeout) {
+        Ok(driver) => {
+            send_all(driver, conn, jobs)?;
+            return Ok(Outcome::Printed);
+        }
+        Err(e) => format!("open {} @{}: {}", conn.port, conn.baud, e),
+    };
+    let Some(spool_dir) = &conn.spool_dir else {
+        return Err(Error::from_reason(open_err));
     };
 
-    let layout = Layout::default();
-    let port = env_port_or_default(payload.port);
-    let baud = env_baud_or_default(payload.baud);
+    let buf = BufDriver::default();
+    send_all(buf.clone(), conn, jobs)?;
+
+    let dir = spool_dir.join(spool_port_dir(&conn.port));
+    std::fs::create_dir_all(&dir)
+        .map_err(|e| Error::from_reason(format!("{}; spool dir {}: {}", open_err, dir.display(), e)))?;
+    let stamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or(0);
+    let path = write_spool_file(&dir, stamp, &buf.bytes())
+        .map_err(|e| Error::from_reason(format!("{}; spool file in {}: {}", open_err, dir.display(), e)))?;
+    Ok(Outcome::Spooled(path))
+}
+
+// `<stamp>.bin`, or `<stamp>-<n>.bin` when another print already took that millisecond. The
+// file is created with create_new so a spool never overwrites one that is still waiting.
+fn write_spool_file(dir: &Path, stamp: u128, bytes: &[u8]) -> std::io::Result<PathBuf> {
+    let mut n = 0u32;
+    loop {
+        let name = if n == 0 { format!("{}.bin", stamp) } else { format!("{}-{}.bin", stamp, n) };
+        let path = dir.join(name);
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                file.write_all(bytes)?;
+                file.sync_all()?;
+                return Ok(path);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => n += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// In-memory driver: collects the byte stream exactly as it would go to the port.
+#[derive(Clone, Default)]
+struct BufDriver {
+    buf: Rc<RefCell<Vec<u8>>>,
+}
+
+impl BufDriver {
+    fn bytes(&self) -> Vec<u8> {
+        self.buf.borrow().clone()
+    }
+}
+
+impl Driver for BufDriver {
+    fn name(&self) -> String {
+        "buffer".to_string()
+    }
+
+    fn write(&self, data: &[u8]) -> escpos::errors::Result<()> {
+        self.buf.borrow_mut().extend_from_slice(data);
+        Ok(())
+    }
+
+    fn read(&self, _buf: &mut [u8]) -> escpos::errors::Result<usize> {
+        Ok(0)
+    }
+
+    fn flush(&self) -> escpos::errors::Result<()> {
+        Ok(())
+    }
+}
+
+// "\\.\COM10" -> "COM10", "/dev/ttyUSB0" -> "dev_ttyUSB0"
+fn spool_port_dir(port: &str) -> String {
+    let name: String = port.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+        .collect();
+    let name = name.trim_matches('_');
+    if name.is_empty() { "default".to_string() } else { name.to_string() }
+}
+
+// Init, profile bytes and every job through one printer, whatever the driver.
+fn send_all<D: Driver>(driver: D, conn: &Connection, jobs: &[PrintJob]) -> Result<()> {
+    let log: Option<Box<dyn Write>> = match &conn.debug_log {
+        None => None,
+        Some(DebugLog::Stderr) => Some(Box::new(std::io::stderr())),
+        // An unwritable log path must not cost the print (or its spool fallback)
+        Some(DebugLog::File(path)) => match OpenOptions::new().create(true).append(true).open(path) {
+            Ok(file) => Some(Box::new(file)),
+            Err(e) => {
+                eprintln!("pos-receipt-printer: debug log {}: {}; dumping to stderr instead", path.display(), e);
+                Some(Box::new(std::io::stderr()))
+            }
+        },
+    };
+
+    // escpos' own debug mode prints to stdout; init and cut are queued through Tx instead, so the
+    // dump holds every byte that goes to the driver and lands in the configured sink.
+    let mut obj = Printer::new(driver, Protocol::default(), None);
+    obj.debug_mode(None);
+    let mut tx = Tx { p: &mut obj, log };
+    tx.custom("init (ESC @)", &[0x1B, 0x40])?;
+    if !conn.init_bytes.is_empty() {
+        tx.custom("printer profile", &conn.init_bytes)?;
+    }
+
+    for job in jobs {
+        send_job(&mut tx, job)?;
+    }
+    Ok(())
+}
+
+// ===================== N-API entry =====================
+#[napi(js_name = "printReceipt")]
+pub async fn print_receipt(payload: JsPrintPayload) -> Result<String> {
+    let conn = connection_from_payload(&payload)?;
+    let job = job_from_payload(payload)?;
 
     // Blocking I/O in spawn_blocking to satisfy Send bounds
     let res = napi::tokio::task::spawn_blocking(move || -> Result<String> {
-        let driver = SerialPortDriver::open(&port, baud, None)
-            .map_err(|e| Error::from_reason(format!("open {} @{}: {}", port, baud, e)))?;
-
-        let mut obj = Printer::new(driver, Protocol::default(), None);
-        obj.debug_mode(None);
-        let mut p = obj.init().map_err(|e| Error::from_reason(e.to_string()))?;
-
-        let gray = render_receipt(&data, &layout);
-
-        // ESC * 24-dot double density
-        let w = gray.width();
-        let n = w as u16;
-        let nL = (n & 0xFF) as u8;
-        let nH = ((n >> 8) & 0xFF) as u8;
-
-        let mut y0 = 0u32;
-        while y0 < gray.height() {
-            let band = pack_esc_star_24(&gray, y0, layout.threshold);
-            p = p.custom(&[0x1B, 0x2A, 33, nL, nH]).map_err(|e| Error::from_reason(e.to_string()))?;
-            p = p.custom(&band).map_err(|e| Error::from_reason(e.to_string()))?;
-            p = p.custom(&[0x0A]).map_err(|e| Error::from_reason(e.to_string()))?;
-            y0 += 24;
-        }
-
-        // Print optional UUID as a 1D Barcode (Code 128)
-        if let Some(uuid_str) = data.uuid {
-            p = p.custom(&[0x0A, 0x0A]).map_err(|e| Error::from_reason(e.to_string()))?; // Margin before barcode
-            
-            // Align Center
-            p = p.custom(&[0x1B, 0x61, 0x01]).map_err(|e| Error::from_reason(e.to_string()))?;
-            
-            // Barcode Height: 40 dots
-            p = p.custom(&[0x1D, 0x68, 40]).map_err(|e| Error::from_reason(e.to_string()))?;
-            // Barcode Width Multiplier: 2 (compact width)
-            p = p.custom(&[0x1D, 0x77, 2]).map_err(|e| Error::from_reason(e.to_string()))?;
-            // HRI Character Print Position: Below Barcode
-            p = p.custom(&[0x1D, 0x48, 0x02]).map_err(|e| Error::from_reason(e.to_string()))?;
-            
-            // Print Barcode: GS k <type: 73 for Code 128> <length> <data>
-            // Note: Code 128 requires subset character at start (e.g. {B for Subset B)
-            // {B in ASCII is `{` (123) and `B` (66)
-            let mut barcode_data = Vec::new();
-            barcode_data.push(0x1D);
-            barcode_data.push(0x6B);
-            barcode_data.push(73); // Code 128
-            
-            let mut payload_bytes = Vec::new();
-            payload_bytes.push(123); // {
-            payload_bytes.push(66);  // B
-            payload_bytes.extend_from_slice(uuid_str.as_bytes());
-            
-            // Barcode system expects the payload length for type 73
-            barcode_data.push(payload_bytes.len() as u8);
-            barcode_data.extend(payload_bytes);
-            
-            p = p.custom(&barcode_data).map_err(|e| Error::from_reason(e.to_string()))?;
-            
-            // Reset Alignment to Left
-            p = p.custom(&[0x1B, 0x61, 0x00]).map_err(|e| Error::from_reason(e.to_string()))?;
-        }
-
-        // Feed 3 lines before cutting so the blade clears the last printed content
-        p = p.custom(&[0x0A, 0x0A, 0x0A]).map_err(|e| Error::from_reason(e.to_string()))?;
-        p = p.print_cut().map_err(|e| Error::from_reason(e.to_string()))?;
-        p.print().map_err(|e| Error::from_reason(e.to_string()))?;
-
-        Ok(format!("✅ Receipt printed on {}", port))
+        match run_jobs(&conn, &[job])? {
+            Outcome::Printed => Ok(format!("✅ Receipt printed on {}", conn.port)),
+            Outcome::Spooled(path) => Ok(format!("📥 Receipt spooled to {} ({} unavailable)", path.display(), conn.port)),
+        }
     })
     .await
     .map_err(|e| napi::Error::from_reason(format!("join error: {e}")))??;
 
     Ok(res)
-}
\ No newline at end of file
+}
+
+// Prints several payloads (receipts and/or labels) over a single connection.
+// Connection settings (port, baud, write timeout, printer profile) are taken from the first payload.
+#[napi(js_name = "printBatch")]
+pub async fn print_batch(payloads: Vec<JsPrintPayload>) -> Result<String> {
+    let first = payloads.first()
+        .ok_or_else(|| Error::new(Status::InvalidArg, "printBatch: no payloads".to_string()))?;
+    let conn = connection_from_payload(first)?;
+    let jobs = payloads.into_iter().map(job_from_payload).collect::<Result<Vec<_>>>()?;
+
+    let res = napi::tokio::task::spawn_blocking(move || -> Result<String> {
+        match run_jobs(&conn, &jobs)? {
+            Outcome::Printed => Ok(format!("✅ {} jobs printed on {}", jobs.len(), conn.port)),
+            Outcome::Spooled(path) => Ok(format!("📥 {} jobs spooled to {} ({} unavailable)", jobs.len(), path.display(), conn.port)),
+        }
+    })
+    .await
+    .map_err(|e| napi::Error::from_reason(format!("join error: {e}")))??;
+
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Left-to-right visual order of `bidi_runs` as draw_shaped_rtl_right lays it out
+    fn visual(shaped: &str) -> String {
+        bidi_runs(shaped).into_iter().rev()
+            .map(|(ltr, seg)| if ltr { seg } else { seg.chars().rev().collect() })
+            .collect()
+    }
+
+    fn item(name: &str, qty: &str, price: &str, total: &str) -> Item {
+        Item {
+            name: name.to_string(), qty_str: qty.to_string(),
+            price: parse_number(price).unwrap() as f32, original_price: None, price_str: price.to_string(),
+            total: parse_number(total).unwrap(), total_str: total.to_string(), original_price_str: None,
+            time: None, components: Vec::new(), thumbnail: None,
+        }
+    }
+
+    fn receipt(items: Vec<Item>, total: &str) -> ReceiptData {
+        ReceiptData {
+            store_name: "مطعم".to_string(),
+            date_time_line: "2024-01-01 10:30".to_string(),
+            invoice_no: "42".to_string(),
+            items,
+            discount: 0.0,
+            total_str: total.to_string(),
+            rounding_adjustment: None,
+            discount_str: String::new(),
+            service_fee: 0.0,
+            service_fee_str: String::new(),
+            delivery_fee: 0.0,
+            delivery_fee_str: String::new(),
+            footer_address: "شارع النيل".to_string(),
+            footer_delivery: "توصيل مجاني".to_string(),
+            footer_phones: String::new(),
+            uuid: None,
+            reconcile_barcode: None,
+            watermark: None,
+            end_marker: None,
+            summary_header: false,
+            order_type: None,
+        }
+    }
+
+    fn png(img: image::RgbaImage) -> Vec<u8> {
+        let mut out = std::io::Cursor::new(Vec::new());
+        image::DynamicImage::ImageRgba8(img).write_to(&mut out, image::ImageOutputFormat::Png).unwrap();
+        out.into_inner()
+    }
+
+    // Opaque black square in the middle of a fully transparent (0,0,0,0) canvas
+    fn transparent_logo(side: u32) -> Vec<u8> {
+        png(image::RgbaImage::from_fn(side, side, |x, y| {
+            let inside = (side / 4..side * 3 / 4).contains(&x) && (side / 4..side * 3 / 4).contains(&y);
+            if inside { image::Rgba([0, 0, 0, 255]) } else { image::Rgba([0, 0, 0, 0]) }
+        }))
+    }
+
+    #[test]
+    fn transparent_pixels_decode_as_paper_white() {
+        let gray = decode_gray("watermark", &transparent_logo(64), WATERMARK_MAX_BYTES, 64, 64).unwrap();
+        assert_eq!(gray.get_pixel(2, 2)[0], 255);
+        assert_eq!(gray.get_pixel(32, 32)[0], 0);
+    }
+
+    #[test]
+    fn oversized_images_are_rejected() {
+        let bytes = transparent_logo(64);
+        assert!(decode_gray("watermark", &bytes, bytes.len() - 1, 64, 64).is_err());
+        let huge = png(image::RgbaImage::new(4096, 2048));
+        assert!(decode_gray("watermark", &huge, usize::MAX, 576, CANVAS_HEIGHT).is_err());
+
+        // A tall, narrow logo is within the pixel cap but must not be scaled past the canvas
+        let tall = png(image::RgbaImage::new(8, 2048));
+        let scaled = decode_gray("watermark", &tall, usize::MAX, 576, CANVAS_HEIGHT).unwrap();
+        assert!(scaled.width() <= 576 && scaled.height() <= CANVAS_HEIGHT, "{:?}", scaled.dimensions());
+    }
+
+    #[test]
+    fn watermark_dots_fill_background_only() {
+        let layout = Layout::default();
+        let data = receipt(vec![item("شاي", "1", "10.00", "10.00")], "10.00");
+        let plain = render_receipt(&data, &layout);
+
+        let solid = GrayImage::from_pixel(layout.paper_width_px, 64, Luma([0]));
+        let marked = render_receipt(&ReceiptData { watermark: Some(Watermark { image: solid, opacity: 0.5 }), ..data.clone() }, &layout);
+        assert_eq!(plain.dimensions(), marked.dimensions());
+        let mut dots = 0;
+        for (p, m) in plain.pixels().zip(marked.pixels()) {
+            if p[0] != 255 { assert_eq!(m[0], p[0], "text pixels are left alone"); }
+            else if m[0] == 0 { dots += 1; }
+        }
+        let white = plain.pixels().filter(|p| p[0] == 255).count();
+        assert!(dots > white / 3 && dots < white * 2 / 3, "{} dots over {} white pixels", dots, white);
+
+        // The transparent border of a logo must not print at all
+        let logo = decode_gray("watermark", &transparent_logo(64), WATERMARK_MAX_BYTES, layout.paper_width_px, CANVAS_HEIGHT).unwrap();
+        let marked = render_receipt(&ReceiptData { watermark: Some(Watermark { image: logo, opacity: 1.0 }), ..data }, &layout);
+        let (w, h) = marked.dimensions();
+        for y in 0..h.min(100) {
+            assert_eq!(marked.get_pixel(w - 1, y)[0], plain.get_pixel(w - 1, y)[0]);
+        }
+    }
+
+    #[test]
+    fn transparent_thumbnail_is_not_a_black_square() {
+        let thumb = decode_thumbnail(&transparent_logo(112)).unwrap();
+        assert_eq!(thumb.dimensions(), (THUMB_MAX_PX, THUMB_MAX_PX));
+        assert_eq!(thumb.get_pixel(2, 2)[0], 255);
+        assert_eq!(thumb.get_pixel(28, 28)[0], 0);
+    }
+
+    #[test]
+    fn thumbnail_prints_at_row_start_and_pushes_name_left() {
+        let layout = Layout::default();
+        let right = layout.paper_width_px;
+        let side = THUMB_MAX_PX;
+        let black = GrayImage::from_pixel(side, side, Luma([0]));
+
+        // Nameless item: the only difference above the (taller) row is the thumbnail itself
+        let bare = item("", "1", "50.00", "50.00");
+        let a = render_receipt(&receipt(vec![bare.clone()], "50.00"), &layout);
+        let b = render_receipt(&receipt(vec![Item { thumbnail: Some(black.clone()), ..bare }], "50.00"), &layout);
+        let top = (0..a.height()).find(|&y| (0..right).any(|x| a.get_pixel(x, y) != b.get_pixel(x, y))).unwrap();
+        for y in top..top + side {
+            for x in right - side..right { assert_eq!(b.get_pixel(x, y)[0], 0); }
+        }
+
+        // Components and the discount line start below the thumbnail, not over it
+        let busy = Item {
+            thumbnail: Some(black.clone()),
+            components: vec!["بطاطس".to_string()],
+            original_price: Some(60.0),
+            original_price_str: Some("60.00".to_string()),
+            ..item("برجر", "1", "50.00", "50.00")
+        };
+        let c = render_receipt(&receipt(vec![busy], "50.00"), &layout);
+        let thumb_top = (0..c.height()).find(|&y| (right - side..right).all(|x| c.get_pixel(x, y)[0] == 0)).unwrap();
+        for y in thumb_top..thumb_top + side {
+            for x in right - side..right { assert_eq!(c.get_pixel(x, y)[0], 0, "thumbnail overdrawn at ({}, {})", x, y); }
+        }
+        for y in thumb_top + side..thumb_top + side + 4 {
+            assert!((0..right).all(|x| c.get_pixel(x, y)[0] == 255), "ink in the pad below the thumbnail at y={}", y);
+        }
+
+        // With a name, the name ink moves left of the thumbnail and its gap
+        let named = item("برجر", "1", "50.00", "50.00");
+        let a = render_receipt(&receipt(vec![named.clone()], "50.00"), &layout);
+        let b = render_receipt(&receipt(vec![Item { thumbnail: Some(black), ..named }], "50.00"), &layout);
+        // The row grows to the thumbnail's bottom, which starts at the name's ink top, plus a 4-dot pad
+        let ink_top = ink_extent(&load_font(&layout), Scale::uniform(layout.fonts.item), &[shape("برجر", layout.script)]).0;
+        assert_eq!(b.height() - a.height(), (ink_top + side as i32 + 4 - (layout.row_gap - 4)) as u32);
+        let rows = top..top + side;
+        let name_ink = |img: &GrayImage, x_range: std::ops::Range<u32>| {
+            rows.clone().any(|y| x_range.clone().any(|x| img.get_pixel(x, y)[0] == 0))
+        };
+        assert!(name_ink(&a, right - side..right));
+        assert!(!name_ink(&b, right - side - 6..right - side));
+        assert!(name_ink(&b, right / 2..right - side - 8));
+    }
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("pos-receipt-printer-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn connection(port: &str) -> Connection {
+        Connection {
+            port: port.to_string(),
+            baud: 9600,
+            write_timeout: None,
+            init_bytes: Vec::new(),
+            spool_dir: None,
+            debug_log: None,
+        }
+    }
+
+    fn receipt_job(finisher: Finisher) -> PrintJob {
+        PrintJob { data: receipt(vec![item("شاي", "1", "10.00", "10.00")], "10.00"), layout: Layout::default(), finisher }
+    }
+
+    #[test]
+    fn unopenable_port_spools_the_byte_stream() {
+        let dir = test_dir("spool");
+        let conn = Connection { spool_dir: Some(dir.clone()), ..connection("/dev/pos-receipt-printer-missing") };
+        let jobs = [receipt_job(Finisher::Cut)];
+
+        let path = match run_jobs(&conn, &jobs).unwrap() {
+            Outcome::Spooled(path) => path,
+            Outcome::Printed => panic!("expected the job to be spooled"),
+        };
+        assert_eq!(path.parent().unwrap(), dir.join("dev_pos-receipt-printer-missing"));
+
+        let expected = BufDriver::default();
+        send_all(expected.clone(), &conn, &jobs).unwrap();
+        assert!(!expected.bytes().is_empty());
+        assert_eq!(std::fs::read(&path).unwrap(), expected.bytes());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn spool_files_in_the_same_millisecond_do_not_overwrite() {
+        let dir = test_dir("spool-collide");
+        std::fs::create_dir_all(&dir).unwrap();
+        let first = write_spool_file(&dir, 1700000000000, b"one").unwrap();
+        let second = write_spool_file(&dir, 1700000000000, b"two").unwrap();
+        assert_ne!(first, second);
+        assert_eq!(std::fs::read(&first).unwrap(), b"one");
+        assert_eq!(std::fs::read(&second).unwrap(), b"two");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    // Bytes from the hex lines of a debug dump, in order
+    fn dumped_bytes(log: &str) -> Vec<u8> {
+        log.lines()
+            .filter(|l| l.starts_with("  "))
+            .flat_map(|l| l.split_whitespace().skip(1).map(|h| u8::from_str_radix(h, 16).unwrap()).collect::<Vec<_>>())
+            .collect()
+    }
+
+    // Command names from the headers of a debug dump, in order
+    fn dumped_commands(log: &str) -> Vec<String> {
+        log.lines()
+            .filter_map(|l| l.strip_prefix("[pos-receipt-printer] "))
+            .map(|l| l[..l.rfind(" (").unwrap()].to_string())
+            .collect()
+    }
+
+    // Sends `jobs` to an in-memory driver; returns the bytes and the debug dump
+    fn dry_run(conn: Connection, jobs: &[PrintJob], name: &str) -> (Vec<u8>, String) {
+        let dir = test_dir(name);
+        std::fs::create_dir_all(&dir).unwrap();
+        let log = dir.join("dump.log");
+        let conn = Connection { debug_log: Some(DebugLog::File(log.clone())), ..conn };
+        let sent = BufDriver::default();
+        send_all(sent.clone(), &conn, jobs).unwrap();
+        let dump = std::fs::read_to_string(&log).unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+        (sent.bytes(), dump)
+    }
+
+    #[test]
+    fn finisher_follows_media_and_cut() {
+        assert!(finisher_for(None, None).unwrap() == Finisher::Cut);
+        assert!(finisher_for(Some("receipt"), Some(false)).unwrap() == Finisher::Feed);
+        assert!(finisher_for(Some("label"), None).unwrap() == Finisher::LabelFeed);
+        assert!(finisher_for(Some("label"), Some(true)).unwrap() == Finisher::Cut);
+        assert!(finisher_for(Some("sticker"), None).is_err());
+    }
+
+    #[test]
+    fn mixed_batch_cuts_the_receipt_and_label_feeds_the_label() {
+        let receipt = job_from_payload(payload(vec![js_item("شاي", "10.00")], "10.00")).unwrap();
+        let label = job_from_payload(JsPrintPayload { media: Some("label".to_string()), ..payload(vec![js_item("قهوة", "15.50")], "15.50") }).unwrap();
+        let (bytes, dump) = dry_run(connection("COM7"), &[receipt, label], "batch");
+
+        let finishers: Vec<String> = dumped_commands(&dump).into_iter()
+            .filter(|c| !matches!(c.as_str(), "init (ESC @)" | "ESC * band" | "band data" | "LF"))
+            .collect();
+        assert_eq!(finishers, ["feed", "cut (GS V 0)", "label feed (GS FF)"]);
+        assert!(bytes.ends_with(&[0x1D, 0x0C]));
+    }
+
+    #[test]
+    fn printer_profile_selects_init_bytes() {
+        let with = |profile: &str| JsPrintPayload { printerProfile: Some(profile.to_string()), ..payload(Vec::new(), "0") };
+        assert!(init_profile(&payload(Vec::new(), "0")).unwrap().is_empty());
+        assert_eq!(init_profile(&with("epson-tm")).unwrap(), PROFILE_EPSON_TM);
+        assert_eq!(init_profile(&with("xprinter")).unwrap(), PROFILE_XPRINTER);
+        assert!(init_profile(&with("custom")).is_err());
+        assert!(init_profile(&with("zebra")).is_err());
+        let custom = JsPrintPayload { initBytes: Some(vec![0x1B, 0x74, 0x11].into()), ..with("custom") };
+        assert_eq!(init_profile(&custom).unwrap(), [0x1B, 0x74, 0x11]);
+    }
+
+    #[test]
+    fn printer_profile_bytes_follow_init() {
+        let p = JsPrintPayload { printerProfile: Some("xprinter".to_string()), ..payload(vec![js_item("شاي", "10.00")], "10.00") };
+        let conn = connection_from_payload(&p).unwrap();
+        let (bytes, _) = dry_run(conn, &[job_from_payload(p).unwrap()], "profile");
+        assert_eq!(&bytes[..2], [0x1B, 0x40]);
+        assert_eq!(&bytes[2..2 + PROFILE_XPRINTER.len()], PROFILE_XPRINTER);
+    }
+
+    #[test]
+    fn write_timeout_reaches_the_serial_open() {
+        let p = JsPrintPayload { writeTimeoutMs: Some(2500), ..payload(vec![js_item("شاي", "10.00")], "10.00") };
+        assert_eq!(connection_from_payload(&p).unwrap().write_timeout, Some(Duration::from_millis(2500)));
+        let p = payload(vec![js_item("شاي", "10.00")], "10.00");
+        assert_eq!(connection_from_payload(&p).unwrap().write_timeout, None);
+    }
+
+    #[test]
+    fn debug_dump_holds_every_byte_sent() {
+        let dir = test_dir("debug");
+        std::fs::create_dir_all(&dir).unwrap();
+        let log = dir.join("dump.log");
+        let conn = Connection {
+            init_bytes: PROFILE_EPSON_TM.to_vec(),
+            debug_log: Some(DebugLog::File(log.clone())),
+            ..connection("COM7")
+        };
+        let sent = BufDriver::default();
+        send_all(sent.clone(), &conn, &[receipt_job(Finisher::Cut)]).unwrap();
+
+        let bytes = sent.bytes();
+        assert!(bytes.starts_with(&[0x1B, 0x40]));
+        assert!(bytes.ends_with(&[0x1D, 0x56, 0x00]));
+        assert_eq!(dumped_bytes(&std::fs::read_to_string(&log).unwrap()), bytes);
+
+        // A log file that can't be opened still prints
+        let conn = Connection { debug_log: Some(DebugLog::File(dir.join("missing").join("dump.log"))), ..conn };
+        let sent = BufDriver::default();
+        send_all(sent.clone(), &conn, &[receipt_job(Finisher::Cut)]).unwrap();
+        assert_eq!(sent.bytes(), bytes);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn combo_components_are_indented_sub_lines_inside_the_name_column() {
+        let layout = Layout::default();
+        let line_h = layout.row_gap - 4;
+        let r_qty = layout.paper_width_px as i32 - (layout.paper_width_px as f32 * layout.cols[0]) as i32;
+        let combo = item("وجبة", "1", "120.00", "120.00");
+        let a = render_receipt(&receipt(vec![combo.clone()], "120.00"), &layout);
+
+        let components = vec![
+            "برجر لحم".to_string(),
+            "بطاطس كبيرة مع صوص الجبن والبصل المقلي والمخلل والهالبينو".to_string(),
+            "مشروب غازي".to_string(),
+        ];
+        let font = load_font(&layout);
+        let s_component = Scale::uniform(layout.fonts.item * 0.82);
+        let wrapped = wrap_mixed_rtl(&font, layout.script, s_component, &components[1], (layout.paper_width_px as f32 * layout.cols[0]) as i32 - 28);
+        assert_eq!(wrapped.len(), 2, "the long component needs a second line");
+
+        let b = render_receipt(&receipt(vec![Item { components, ..combo }], "120.00"), &layout);
+        let extra = b.height() - a.height();
+        assert_eq!(extra, 4 * line_h as u32);
+
+        let top = (0..a.height()).find(|&y| (0..a.width()).any(|x| a.get_pixel(x, y) != b.get_pixel(x, y))).unwrap();
+        for y in top..top + extra - 10 {
+            for x in 0..b.width() {
+                if b.get_pixel(x, y)[0] == 0 {
+                    assert!(x as i32 >= r_qty && (x as i32) < b.width() as i32 - 28 + 2, "component ink at ({}, {})", x, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn reconcile_payload_is_number_delimiter_total() {
+        assert_eq!(reconcile_payload("123456", "97.2", "|").unwrap(), "123456|97.20");
+        assert_eq!(reconcile_payload(" INV-0042 ", "٩٧٫٢٠", ";").unwrap(), "INV-0042;97.20");
+        assert_eq!(reconcile_payload("فاتورة رقم: 42", "10", "|").unwrap(), "42|10.00");
+        assert!(reconcile_payload("فاتورة", "10", "|").is_err());
+        assert!(reconcile_payload("42", "10", "،").is_err());
+
+        // 23 characters fit across 576 dots at GS w 2; longer numbers are refused, not truncated
+        assert_eq!(reconcile_payload("12345678901234567", "97.2", "|").unwrap().len(), 23);
+        assert!(reconcile_payload("123456789012345678", "97.2", "|").is_err());
+    }
+
+    #[test]
+    fn code128_escapes_brace() {
+        let bytes = code128_bytes("a{b").unwrap();
+        let start = bytes.windows(3).position(|w| w == [0x1D, 0x6B, 73]).unwrap() + 3;
+        assert_eq!(bytes[start], 6);
+        assert_eq!(&bytes[start + 1..start + 7], b"{Ba{{b");
+
+        // "{B" + 126 escaped braces is 254 bytes; one more brace would not fit the length byte
+        assert_eq!(code128_bytes(&"{".repeat(126)).unwrap().len(), bytes.len() - 6 + 254);
+        assert!(code128_bytes(&"{".repeat(127)).is_err());
+    }
+
+    fn js_item(name: &str, total: &str) -> JsItem {
+        JsItem {
+            name: name.to_string(), qty: "1".to_string(), price: total.to_string(), total: total.to_string(),
+            originalPrice: None, time: None, components: None, image: None,
+        }
+    }
+
+    fn payload(items: Vec<JsItem>, total: &str) -> JsPrintPayload {
+        JsPrintPayload {
+            title: "مطعم".to_string(),
+            time: "2024-01-01 10:30".to_string(),
+            number: "42".to_string(),
+            items,
+            total: total.to_string(),
+            discount: None,
+            serviceFee: None,
+            deliveryFee: None,
+            footer: JsFooter { address: "شارع النيل".to_string(), lastLine: "توصيل مجاني".to_string(), phones: None },
+            uuid: None,
+            watermark: None,
+            endMarker: None,
+            summaryHeader: None,
+            orderType: None,
+            reconcileBarcode: None,
+            reconcileDelimiter: None,
+            media: None,
+            cut: None,
+            printerProfile: None,
+            totalMismatchPolicy: None,
+            cashRounding: None,
+            script: None,
+            runningBalance: None,
+            autoTrimBottom: None,
+            autoTrimMargin: None,
+            fontBytes: None,
+            initBytes: None,
+            port: None,
+            baud: None,
+            writeTimeoutMs: None,
+            spoolOnFailure: None,
+            spoolDir: None,
+            debug: None,
+            debugLog: None,
+        }
+    }
+
+    #[test]
+    fn cash_rounding_to_nearest_005() {
+        let (rounded, adjustment) = round_cash(12.47, 0.05).unwrap();
+        assert_eq!(format!("{:.2}", rounded), "12.45");
+        assert_eq!(format!("{:.2}", adjustment), "-0.02");
+        assert!(round_cash(12.45, 0.05).is_none());
+
+        let job = job_from_payload(JsPrintPayload { cashRounding: Some(0.05), ..payload(vec![js_item("شاي", "12.47")], "12.47") }).unwrap();
+        assert_eq!(job.data.total_str, "12.45");
+        assert_eq!(job.data.rounding_adjustment.as_deref(), Some("-0.02"));
+    }
+
+    #[test]
+    fn cash_rounding_rejects_non_finite_steps() {
+        for step in [f64::INFINITY, f64::NAN, 0.0, -0.05] {
+            let p = JsPrintPayload { cashRounding: Some(step), ..payload(vec![js_item("شاي", "12.47")], "12.47") };
+            assert!(job_from_payload(p).is_err(), "step {}", step);
+        }
+    }
+
+    #[test]
+    fn error_policy_rejects_total_off_by_050() {
+        let items = || vec![js_item("شاي", "10.00"), js_item("قهوة", "15.50")];
+        let policy = |p: JsPrintPayload| JsPrintPayload { totalMismatchPolicy: Some("error".to_string()), ..p };
+
+        let err = job_from_payload(policy(payload(items(), "26.00"))).err().unwrap();
+        assert_eq!(err.status, Status::InvalidArg);
+        assert!(err.reason.contains("25.50"), "{}", err.reason);
+        assert!(job_from_payload(policy(payload(items(), "25.50"))).is_ok());
+
+        // An unparseable item total is reported as such, not blamed on the supplied total
+        let mut bad = items();
+        bad[1].total = "خمسة عشر".to_string();
+        let err = job_from_payload(policy(payload(bad, "25.50"))).err().unwrap();
+        assert!(err.reason.contains("قهوة"), "{}", err.reason);
+    }
+
+    #[test]
+    fn recompute_policy_needs_parseable_amounts() {
+        let recompute = |p: JsPrintPayload| JsPrintPayload { totalMismatchPolicy: Some("recompute".to_string()), ..p };
+        let job = job_from_payload(recompute(payload(vec![js_item("شاي", "10.00"), js_item("قهوة", "15.50")], "0"))).unwrap();
+        assert_eq!(job.data.total_str, "25.50");
+
+        let bad = JsPrintPayload { discount: Some("n/a".to_string()), ..payload(vec![js_item("شاي", "10.00")], "10.00") };
+        assert!(job_from_payload(recompute(bad)).is_err());
+    }
+
+    #[test]
+    fn large_totals_keep_their_cents() {
+        // Five lines of 65536.01: summed in f32 this comes out as 327680.03
+        let items = || (0..5).map(|_| js_item("شاي", "65536.01")).collect::<Vec<_>>();
+        let with_policy = |policy: &str, total: &str| job_from_payload(JsPrintPayload {
+            totalMismatchPolicy: Some(policy.to_string()),
+            ..payload(items(), total)
+        });
+        assert_eq!(with_policy("recompute", "0").unwrap().data.total_str, "327680.05");
+        assert!(with_policy("error", "327680.05").is_ok());
+        assert!(with_policy("error", "327680.07").is_err());
+
+        let job = with_policy("trust", "327680.05").unwrap();
+        assert_eq!(format!("{:.2}", running_totals(&job.data.items)[4]), "327680.05");
+    }
+
+    // First and last row holding ink, if any
+    fn inked_rows(img: &GrayImage) -> Option<(u32, u32)> {
+        let inked = |y: u32| (0..img.width()).any(|x| img.get_pixel(x, y)[0] != 255);
+        let first = (0..img.height()).find(|&y| inked(y))?;
+        Some((first, last_content_row(img)?))
+    }
+
+    #[test]
+    fn ink_extent_matches_rendered_glyphs() {
+        let layout = Layout::default();
+        let font = load_font(&layout);
+        for size in [32.0, 42.0, 66.0] {
+            let scale = Scale::uniform(size);
+            let shaped = shape("الإجمالي 25.50", Script::Arabic);
+            let mut img = GrayImage::from_pixel(576, 200, Luma([255]));
+            draw_shaped_rtl_right(&mut img, &font, scale, &shaped, 560, 40);
+            let (first, last) = inked_rows(&img).unwrap();
+            let (top, bottom) = ink_extent(&font, scale, &[shaped]);
+            assert!((first as i32 - (40 + top)).abs() <= 1, "top {} vs {} at {}", first, 40 + top, size);
+            assert!((last as i32 + 1 - (40 + bottom)).abs() <= 1, "bottom {} vs {} at {}", last + 1, 40 + bottom, size);
+        }
+    }
+
+    #[test]
+    fn summary_box_encloses_its_rows_above_the_items() {
+        let layout = Layout::default();
+        let items = vec![item("شاي", "1", "10.00", "10.00"), item("قهوة", "2", "15.50", "31.00"), item("ماء", "1", "5.00", "5.00")];
+        let plain = render_receipt(&receipt(items.clone(), "46.00"), &layout);
+        let data = ReceiptData { summary_header: true, order_type: Some("سفري".to_string()), ..receipt(items, "46.00") };
+        let img = render_receipt(&data, &layout);
+        assert!(img.height() > plain.height());
+
+        // Box edges are the only near-solid rows
+        let w = img.width();
+        let solid: Vec<u32> = (0..img.height())
+            .filter(|&y| (0..w).filter(|&x| img.get_pixel(x, y)[0] == 0).count() as u32 > w * 4 / 5)
+            .collect();
+        assert_eq!(solid.len(), 4, "two 2px edges: {:?}", solid);
+        let (top, bottom) = (solid[0], solid[3]);
+
+        // Inside the box: ink on the three rows, none within the padding next to the edges
+        let inner = |y: u32| (10..w - 10).any(|x| img.get_pixel(x, y)[0] != 255);
+        assert!((top + 2..bottom - 1).any(inner));
+        for y in (top + 2..top + 2 + 5).chain(bottom - 1 - 5..bottom - 1) {
+            assert!(!inner(y), "ink touches the box edge at row {}", y);
+        }
+
+        // Without an order type the box holds exactly the two total rows
+        let data = ReceiptData { order_type: None, ..data };
+        let img = render_receipt(&data, &layout);
+        let solid: Vec<u32> = (0..img.height())
+            .filter(|&y| (0..w).filter(|&x| img.get_pixel(x, y)[0] == 0).count() as u32 > w * 4 / 5)
+            .collect();
+        assert_eq!(solid.len(), 4, "two 2px edges: {:?}", solid);
+        let (top, bottom) = (solid[0], solid[3]);
+
+        let s_sum = Scale::uniform(layout.fonts.header_cols);
+        let mut reference = GrayImage::from_pixel(w, 200, Luma([255]));
+        let right = w as i32 - 12;
+        draw_total_row(&mut reference, &bundled_font(), s_sum, "عدد الأصناف", "3", right, 40);
+        draw_total_row(&mut reference, &bundled_font(), s_sum, "الإجمالي", "46.00", right, 40 + layout.row_gap);
+        let (ref_first, _) = inked_rows(&reference).unwrap();
+        let interior = 8..w - 8;
+        let rec_first = (top + 2..bottom - 1).find(|&y| interior.clone().any(|x| img.get_pixel(x, y)[0] != 255)).unwrap();
+        for y in top + 2..bottom - 1 {
+            for x in interior.clone() {
+                let expected = reference.get_pixel(x, y + ref_first - rec_first)[0];
+                assert_eq!(img.get_pixel(x, y)[0], expected, "box content differs at ({}, {})", x, y);
+            }
+        }
+
+        // Above the box the header is untouched; from the box bottom down the plain receipt (headings
+        // first, then the item rows) follows, just shifted down
+        let shift = img.height() - plain.height();
+        for x in 0..w {
+            for y in 0..top { assert_eq!(img.get_pixel(x, y), plain.get_pixel(x, y)); }
+            for y in bottom + 1..img.height() { assert_eq!(img.get_pixel(x, y), plain.get_pixel(x, y - shift)); }
+        }
+    }
+
+    #[test]
+    fn gray_scratch_matches_the_old_rgb_path() {
+        let font = load_font(&Layout::default());
+        // Overlapping runs so anti-aliased edges are blended over already-gray pixels too
+        let draws = [
+            (shape("إجمالي الفاتورة", Script::Arabic), 48.0, 200, 10),
+            (shape("لا إله إلا الله", Script::Arabic), 44.0, 210, 30),
+            ("97.20".to_string(), 66.0, 20, 0),
+            ("12:30 ✓".to_string(), 26.0, 40, 40),
+        ];
+        let mut rgb = image::RgbImage::from_pixel(576, 120, image::Rgb([255, 255, 255]));
+        let mut gray = GrayImage::from_pixel(576, 120, Luma([255]));
+        for (s, size, x, y) in &draws {
+            draw_text_mut(&mut rgb, image::Rgb([0, 0, 0]), *x, *y, Scale::uniform(*size), &font, s);
+            draw_crisp(&mut gray, s, *x, *y, Scale::uniform(*size), &font);
+        }
+        let old = image::DynamicImage::ImageRgb8(rgb).to_luma8();
+        assert!(gray.pixels().any(|p| p[0] != 0 && p[0] != 255), "expected anti-aliased pixels");
+        assert_eq!(old.as_raw(), gray.as_raw());
+    }
+
+    const LIGATURE_NAME: &str = "سلطة فلافل بالليمون والزعتر البلدي الأصلي مع لبنة لا تنسى";
+
+    #[test]
+    fn wrapped_lines_fit_when_drawn() {
+        let font = load_font(&Layout::default());
+        let scale = Scale::uniform(44.0);
+        for max_w in [180, 230, 300] {
+            let lines = wrap_mixed_rtl(&font, Script::Arabic, scale, LIGATURE_NAME, max_w);
+            for ln in &lines {
+                assert!(shaped_width(scale, &font, Script::Arabic, ln) <= max_w, "{:?} wider than {}", ln, max_w);
+                let mut img = GrayImage::from_pixel(576, 80, Luma([255]));
+                draw_mixed_rtl_right(&mut img, &font, Script::Arabic, scale, ln, 560, 0);
+                let left = (0..576).find(|&x| (0..80).any(|y| img.get_pixel(x, y)[0] != 255)).unwrap();
+                assert!(left as i32 >= 560 - max_w - 2, "{:?} drawn from x={} at max_w {}", ln, left, max_w);
+            }
+            // Greedy: the next word would not have fitted on the first line
+            if lines.len() == 2 && !lines[1].ends_with('…') {
+                let next = lines[1].split_whitespace().next().unwrap();
+                assert!(shaped_width(scale, &font, Script::Arabic, &format!("{} {}", lines[0], next)) > max_w);
+            }
+        }
+    }
+
+    #[test]
+    fn ellipsis_only_when_text_was_cut() {
+        let font = load_font(&Layout::default());
+        let scale = Scale::uniform(44.0);
+        let full = shaped_width(scale, &font, Script::Arabic, LIGATURE_NAME);
+
+        let two = wrap_mixed_rtl(&font, Script::Arabic, scale, LIGATURE_NAME, full * 2 / 3);
+        assert_eq!(two.len(), 2);
+        assert!(!two[1].ends_with('…'));
+        assert_eq!(format!("{} {}", two[0], two[1]), LIGATURE_NAME);
+
+        let cut = wrap_mixed_rtl(&font, Script::Arabic, scale, LIGATURE_NAME, full / 4);
+        assert_eq!(cut.len(), 2);
+        assert!(cut[1].ends_with('…'));
+        assert!(shaped_width(scale, &font, Script::Arabic, &cut[1]) <= full / 4);
+    }
+
+    #[test]
+    fn last_content_row_skips_trailing_white() {
+        let mut img = GrayImage::from_pixel(40, 100, Luma([255]));
+        assert_eq!(last_content_row(&img), None);
+        img.put_pixel(3, 10, Luma([0]));
+        img.put_pixel(30, 61, Luma([200]));
+        assert_eq!(last_content_row(&img), Some(61));
+    }
+
+    #[test]
+    fn auto_trim_crops_at_the_last_content_row() {
+        // A generous bottom margin leaves whitespace below the footer
+        let layout = Layout { margin_bottom: 120, ..Layout::default() };
+        let data = receipt(vec![item("شاي", "1", "10.00", "10.00")], "10.00");
+        let untrimmed = render_receipt(&data, &layout);
+        let last = last_content_row(&untrimmed).unwrap();
+        assert!(untrimmed.height() > last + 1 + 120);
+
+        let flush = render_receipt(&data, &Layout { auto_trim_bottom: true, ..layout.clone() });
+        assert_eq!(flush.height(), last + 1);
+        assert_eq!(last_content_row(&flush), Some(last));
+
+        // autoTrimMargin keeps that many blank dots under the last row
+        let job = job_from_payload(JsPrintPayload {
+            autoTrimBottom: Some(true),
+            autoTrimMargin: Some(24),
+            ..payload(vec![js_item("شاي", "10.00")], "10.00")
+        }).unwrap();
+        assert_eq!(job.layout.auto_trim_margin, 24);
+        let padded = render_receipt(&data, &Layout { auto_trim_bottom: true, auto_trim_margin: 24, ..layout });
+        assert_eq!(padded.height(), last + 1 + 24);
+        assert_eq!(last_content_row(&padded), Some(last));
+    }
+
+    #[test]
+    fn order_time_sits_left_of_the_name_on_the_first_line() {
+        let layout = Layout::default();
+        let font = load_font(&layout);
+        let r_qty = layout.paper_width_px as i32 - (layout.paper_width_px as f32 * layout.cols[0]) as i32;
+        let time_w = 4 + measure(Scale::uniform(layout.fonts.item_time), &font, "10:45") + 8;
+
+        let plain = item("شاي بالنعناع", "1", "10.00", "10.00");
+        let a = render_receipt(&receipt(vec![plain.clone()], "10.00"), &layout);
+        let b = render_receipt(&receipt(vec![Item { time: Some("10:45".to_string()), ..plain }], "10.00"), &layout);
+        assert_eq!(a.dimensions(), b.dimensions());
+
+        let diff: Vec<(u32, u32)> = (0..a.height())
+            .flat_map(|y| (0..a.width()).map(move |x| (x, y)))
+            .filter(|&(x, y)| a.get_pixel(x, y) != b.get_pixel(x, y))
+            .collect();
+        assert!(!diff.is_empty());
+        let (top, bottom) = (diff.iter().map(|d| d.1).min().unwrap(), diff.iter().map(|d| d.1).max().unwrap());
+        assert!(((bottom - top) as i32) < layout.row_gap - 4, "time spans rows {}..{}", top, bottom);
+        for (x, _) in diff {
+            assert!((x as i32) >= r_qty && (x as i32) < r_qty + time_w, "time ink at x={}", x);
+        }
+    }
+
+    #[test]
+    fn end_marker_row_replaces_tofu_and_fills_the_width() {
+        let layout = Layout::default();
+        let font = load_font(&layout);
+        let scale = Scale::uniform(layout.fonts.footer);
+        assert_eq!(substitute_tofu(&font, "✂- "), "-- ");
+
+        let unit = "✂- - ";
+        let row = marker_row(&font, layout.script, scale, unit, 576);
+        assert!(!row.contains('✂'));
+        assert!(shaped_width(scale, &font, layout.script, &row) <= 576);
+        assert!(shaped_width(scale, &font, layout.script, &format!("{}{}", row, substitute_tofu(&font, unit))) > 576);
+        assert!(last_content_row(&render_end_marker(unit, &layout)).is_some());
+    }
+
+    #[test]
+    fn end_marker_is_the_last_raster_before_the_cut() {
+        let p = JsPrintPayload {
+            uuid: Some("3f2a9c".to_string()),
+            endMarker: Some("✂- - ".to_string()),
+            ..payload(vec![js_item("شاي", "10.00")], "10.00")
+        };
+        let (_, dump) = dry_run(connection("COM7"), &[job_from_payload(p).unwrap()], "end-marker");
+        let commands = dumped_commands(&dump);
+        let barcode = commands.iter().position(|c| c == "uuid barcode").unwrap();
+        assert!(commands[barcode..].iter().any(|c| c == "ESC * band"));
+        assert_eq!(&commands[commands.len() - 3..], ["LF", "feed", "cut (GS V 0)"]);
+    }
+
+    #[test]
+    fn fees_get_rows_and_count_toward_the_total() {
+        let p = JsPrintPayload {
+            serviceFee: Some("5.00".to_string()),
+            deliveryFee: Some("10.00".to_string()),
+            totalMismatchPolicy: Some("recompute".to_string()),
+            ..payload(vec![js_item("شاي", "20.00")], "20.00")
+        };
+        let job = job_from_payload(p).unwrap();
+        assert_eq!(job.data.total_str, "35.00");
+
+        let layout = Layout::default();
+        let plain = render_receipt(&ReceiptData { service_fee: 0.0, delivery_fee: 0.0, ..job.data.clone() }, &layout);
+        let one = render_receipt(&ReceiptData { delivery_fee: 0.0, ..job.data.clone() }, &layout);
+        let both = render_receipt(&job.data, &layout);
+        let row = (layout.row_gap - 6) as u32;
+        assert_eq!(one.height(), plain.height() + row);
+        assert_eq!(both.height(), plain.height() + 2 * row);
+    }
+
+    #[test]
+    fn hebrew_skips_arabic_shaping_and_orders_runs_rtl() {
+        assert_eq!(shape("שלום 42", Script::Hebrew), "שלום 42");
+        assert_eq!(shape("שלום 42", Script::None), "שלום 42");
+        assert_ne!(shape("شاي", Script::Arabic), "شاي");
+        assert!(has_rtl("שלום"));
+        assert_eq!(bidi_runs("שלום 42"), [(false, "שלום ".to_string()), (true, "42".to_string())]);
+
+        let job = job_from_payload(JsPrintPayload { script: Some("hebrew".to_string()), ..payload(vec![js_item("קפה", "12.00")], "12.00") }).unwrap();
+        assert!(job.layout.script == Script::Hebrew);
+
+        // The Hebrew run is reversed and placed right of the number; the bundled font has no Hebrew
+        // glyphs, so the drawn ink is not checked here
+        assert_eq!(visual("שלום 42"), "42 םולש");
+    }
+
+    #[test]
+    fn built_in_labels_keep_the_bundled_font() {
+        let bundled = Layout::default();
+        let font_bytes: &'static [u8] = include_bytes!("fonts/NotoSansArabic-Black.ttf");
+        let custom = Layout { font: Font::try_from_bytes(font_bytes), ..Layout::default() };
+        assert!(custom.font.is_some());
+
+        // No payload text: headings, totals labels and amounts only
+        let labels_only = ReceiptData {
+            store_name: String::new(),
+            date_time_line: String::new(),
+            invoice_no: String::new(),
+            footer_address: String::new(),
+            footer_delivery: String::new(),
+            discount: 5.0,
+            discount_str: "5.00".to_string(),
+            summary_header: true,
+            ..receipt(vec![], "45.00")
+        };
+        assert_eq!(render_receipt(&labels_only, &custom).as_raw(), render_receipt(&labels_only, &bundled).as_raw());
+
+        // Payload text does switch to the payload font
+        let named = ReceiptData { store_name: "مطعم".to_string(), ..labels_only };
+        assert_ne!(render_receipt(&named, &custom).as_raw(), render_receipt(&named, &bundled).as_raw());
+    }
+
+    #[test]
+    fn running_balance_accumulates_per_row() {
+        let items = vec![item("شاي", "1", "10.00", "10.00"), item("قهوة", "2", "15.50", "31.00"), item("ماء", "1", "5.00", "5.00")];
+        let shown: Vec<String> = running_totals(&items).iter().map(|b| format!("{:.2}", b)).collect();
+        assert_eq!(shown, ["10.00", "41.00", "46.00"]);
+
+        // The balance column only changes the layout when asked for
+        let layout = Layout::default();
+        let data = receipt(items, "46.00");
+        let with = render_receipt(&data, &Layout { running_balance: true, ..layout.clone() });
+        let without = render_receipt(&data, &layout);
+        assert_eq!(with.dimensions(), without.dimensions());
+        assert_ne!(with.as_raw(), without.as_raw());
+
+        // A five-digit balance lands fully on the paper, clear of a four-digit row total
+        let font = load_font(&layout);
+        let s_item = Scale::uniform(layout.fonts.item);
+        let inner_w = layout.paper_width_px as i32 - layout.margin_h * 2;
+        let c = columns(&layout.cols_running, inner_w, layout.margin_h + inner_w);
+        let ink_cols = |text: &str, right: i32| {
+            let mut img = GrayImage::from_pixel(layout.paper_width_px, 80, Luma([255]));
+            draw_ltr_right(&mut img, &font, s_item, text, right, 10);
+            let inked = |x: u32| (0..img.height()).any(|y| img.get_pixel(x, y)[0] != 255);
+            let xs: Vec<u32> = (0..img.width()).filter(|&x| inked(x)).collect();
+            (xs[0], xs[xs.len() - 1])
+        };
+        let (balance_left, balance_right) = ink_cols("99999.99", c.r_balance);
+        let (total_left, _) = ink_cols("9999.99", c.r_total);
+        assert!(balance_left as i32 > layout.margin_h, "balance clipped at x={}", balance_left);
+        assert!(balance_right < total_left, "balance ends at {}, total starts at {}", balance_right, total_left);
+    }
+
+    #[test]
+    fn large_total_value_is_centered_on_the_label() {
+        let font = load_font(&Layout::default());
+        let ink_center = |scale: Scale, y: i32| {
+            let mut img = GrayImage::from_pixel(300, 400, Luma([255]));
+            draw_crisp(&mut img, "0", 20, y, scale, &font);
+            let (first, last) = inked_rows(&img).unwrap();
+            (first + last + 1) as f32 / 2.0
+        };
+        for (label, value) in [(48.0, 66.0), (48.0, 120.0), (30.0, 96.0)] {
+            let (small, big) = (Scale::uniform(label), Scale::uniform(value));
+            let dy = center_offset(&font, small, big);
+            assert!(dy < 0, "a larger value moves up");
+            let diff = ink_center(small, 100) - ink_center(big, 100 + dy);
+            assert!(diff.abs() <= 1.0, "{} vs {}: off by {}", label, value, diff);
+        }
+    }
+
+    #[test]
+    fn mixed_invoice_number_keeps_colon_with_arabic() {
+        let runs = bidi_runs("فاتورة رقم: 42");
+        assert_eq!(runs, [(false, "فاتورة رقم: ".to_string()), (true, "42".to_string())]);
+        let expected: String = "42".chars().chain("فاتورة رقم: ".chars().rev()).collect();
+        assert_eq!(visual("فاتورة رقم: 42"), expected);
+    }
+
+    #[test]
+    fn neutrals_between_ltr_stay_ltr() {
+        assert_eq!(bidi_runs("12.50"), [(true, "12.50".to_string())]);
+        assert_eq!(bidi_runs("2024-01-01 10:30"), [(true, "2024-01-01 10:30".to_string())]);
+    }
+}